@@ -0,0 +1,171 @@
+// Compare the arena based merge with the previous `Rc<RefCell<Domain>>` trie
+// on a synthetic input of 2M rules:
+//     cargo bench --bench merge
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
+
+use easy_adblock_for_mosdns::libs::{
+    rule::{Rule, RuleType},
+    tools::merge_and_remove_duplicates,
+};
+
+const RULES: usize = 2_000_000;
+const TLDS: [&str; 8] = ["com", "net", "org", "cn", "io", "xyz", "top", "info"];
+
+fn synthetic_rules() -> Vec<Vec<Rule>> {
+    let mut seed: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut rules_vec: Vec<Vec<Rule>> = (0..4).map(|_| vec![]).collect();
+    for i in 0..RULES {
+        let random = next();
+        let mut rule_content = format!(
+            "s{:x}.{}",
+            random % 400_000,
+            TLDS[(random >> 20) as usize % TLDS.len()]
+        );
+        for _ in 0..(random >> 24) % 3 {
+            rule_content = format!("h{:x}.{}", next() % 4096, rule_content);
+        }
+        let rule_type = if (random >> 32) % 4 == 0 {
+            RuleType::Domain
+        } else {
+            RuleType::Full
+        };
+        rules_vec[i % 4].push(Rule::new(rule_type, rule_content));
+    }
+    rules_vec
+}
+
+// Each implementation runs in its own process so that the peak memory
+// (VmHWM) of one doesn't hide the other.
+fn main() {
+    let implementation = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    match implementation.as_deref() {
+        Some("legacy") => run(
+            "Rc<RefCell<Domain>> trie",
+            legacy_merge_and_remove_duplicates,
+        ),
+        Some("arena") => run("arena trie", merge_and_remove_duplicates),
+        _ => {
+            println!("input: {} rules", RULES);
+            for implementation in ["legacy", "arena"] {
+                let status = std::process::Command::new(std::env::current_exe().unwrap())
+                    .arg(implementation)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+        }
+    }
+}
+
+fn run(name: &str, merge: fn(Vec<Vec<Rule>>) -> Vec<Rule>) {
+    let rules_vec = synthetic_rules();
+    let start = Instant::now();
+    let rules = merge(rules_vec);
+    let elapsed = start.elapsed();
+    let peak_memory = std::fs::read_to_string("/proc/self/status")
+        .unwrap_or_default()
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))
+        .map(|line| line.trim_start_matches("VmHWM:").trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!(
+        "{:<24} {:>8.2?}, {} rules, peak memory {}",
+        name,
+        elapsed,
+        rules.len(),
+        peak_memory
+    );
+}
+
+#[derive(Debug)]
+struct Domain {
+    domain: String,
+    domain_type: bool,
+    is_rule: bool,
+    children: HashMap<String, Rc<RefCell<Domain>>>,
+}
+
+fn legacy_merge_and_remove_duplicates(rules_vec: Vec<Vec<Rule>>) -> Vec<Rule> {
+    let rules_tree = Rc::new(RefCell::new(Domain {
+        domain: "".to_string(),
+        domain_type: false,
+        is_rule: false,
+        children: HashMap::new(),
+    }));
+
+    for rules in rules_vec {
+        for rule in rules {
+            let mut rule_domain = rule.rule_content.split('.').collect::<Vec<&str>>();
+            rule_domain.reverse();
+            let rule_type = rule.rule_type;
+            let mut current_domain = rules_tree.clone();
+            for domain in rule_domain {
+                let mut found = false;
+                let mut current_domain_temp = current_domain.clone();
+                if current_domain.borrow().children.contains_key(domain) {
+                    current_domain_temp = current_domain.borrow().children[domain].clone();
+                    found = true;
+                }
+                current_domain = current_domain_temp.clone();
+                if !found {
+                    let new_domain = Domain {
+                        domain: domain.to_string(),
+                        domain_type: false,
+                        is_rule: false,
+                        children: HashMap::new(),
+                    };
+                    current_domain
+                        .borrow_mut()
+                        .children
+                        .insert(domain.to_string(), Rc::new(RefCell::new(new_domain)));
+                    current_domain_temp = current_domain.borrow().children[domain].clone();
+                    current_domain = current_domain_temp.clone();
+                }
+            }
+            current_domain.borrow_mut().domain_type = rule_type == RuleType::Domain;
+            current_domain.borrow_mut().is_rule = true;
+            if current_domain.borrow_mut().domain_type {
+                current_domain.borrow_mut().children = HashMap::new();
+            }
+        }
+    }
+    let mut rules = vec![];
+
+    let mut stack: Vec<(Rc<RefCell<Domain>>, usize)> = vec![];
+    stack.push((rules_tree.clone(), 0));
+    let mut full_domain: Vec<String> = vec![];
+    while let Some(current_domain) = stack.pop() {
+        while full_domain.len() > current_domain.1 {
+            full_domain.pop();
+        }
+        full_domain.push(current_domain.0.borrow().domain.clone());
+        if current_domain.0.borrow().is_rule {
+            let mut full_domain_t = full_domain.clone();
+            full_domain_t.reverse();
+            full_domain_t.pop();
+            rules.push(Rule::new(
+                if current_domain.0.borrow().domain_type {
+                    RuleType::Domain
+                } else {
+                    RuleType::Full
+                },
+                full_domain_t.join("."),
+            ));
+        }
+        stack.extend(
+            current_domain
+                .0
+                .borrow()
+                .children
+                .values()
+                .map(|v| (v.clone(), current_domain.1 + 1)),
+        );
+    }
+    rules
+}
@@ -1,5 +1,21 @@
+pub mod archive;
+pub mod build;
+pub mod cache;
 pub mod config;
+pub mod diff;
 pub mod download;
+pub mod error;
+pub mod format;
+pub mod geosite;
+pub mod integrity;
+pub mod lock;
+pub mod matcher;
+pub mod output;
+pub mod query;
+pub mod report;
 pub mod request;
 pub mod rule;
+pub mod rule_set;
+pub mod stats;
 pub mod tools;
+pub mod validate;
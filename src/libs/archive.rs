@@ -0,0 +1,326 @@
+use std::io::{Cursor, Read, Write};
+
+use super::error::{Error, Result};
+
+// Limits guarding against decompression bombs and archives holding themselves
+const MAX_LAYERS: usize = 4; // `.tar.gz` is two
+const MAX_SIZE: u64 = 256 << 20; // bytes of any unpacked layer
+
+// How the bytes of a source are packed, found by `detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packing {
+    Plain,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    Zip,
+    Tar,
+}
+
+impl Packing {
+    // By the magic bytes, or the extension of `location` for tar files
+    // written without the ustar magic
+    pub fn detect(bytes: &[u8], location: &str) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Packing::Gzip
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Packing::Xz
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Packing::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Packing::Bzip2
+        } else if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+            Packing::Zip
+        } else if bytes.get(257..262) == Some(b"ustar")
+            || (location.ends_with(".tar") && !bytes.is_empty() && bytes.len().is_multiple_of(512))
+        {
+            Packing::Tar
+        } else {
+            Packing::Plain
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Packing::Plain => "plain",
+            Packing::Gzip => "gzip",
+            Packing::Xz => "xz",
+            Packing::Zstd => "zstd",
+            Packing::Bzip2 => "bzip2",
+            Packing::Zip => "zip",
+            Packing::Tar => "tar",
+        }
+    }
+}
+
+// The text of a source, decompressed and taken out of archives as often as
+// needed (`.tar.gz` is gunzipped, then `member` is read from the tar).
+// Without `member` an archive must hold exactly one file.
+pub fn unpack(location: &str, bytes: Vec<u8>, member: Option<&str>) -> Result<String> {
+    unpack_limited(location, bytes, member, MAX_SIZE)
+}
+
+fn unpack_limited(
+    location: &str,
+    mut bytes: Vec<u8>,
+    member: Option<&str>,
+    max_size: u64,
+) -> Result<String> {
+    let mut member = member;
+    let mut layers = 0;
+    loop {
+        let packing = Packing::detect(&bytes, location);
+        let fail =
+            |message: String| Error::parse(location, format!("{}: {}", packing.name(), message));
+        if packing != Packing::Plain && layers == MAX_LAYERS {
+            return Err(fail(format!("packed more than {} layers deep", MAX_LAYERS)));
+        }
+        layers += 1;
+        bytes = match packing {
+            Packing::Plain => break,
+            Packing::Gzip => {
+                let mut decoder = flate2::read::MultiGzDecoder::new(&bytes[..]);
+                read_all(&mut decoder, max_size).map_err(fail)?
+            }
+            Packing::Xz => {
+                let mut output = LimitedWriter {
+                    bytes: vec![],
+                    max_size,
+                };
+                lzma_rs::xz_decompress(&mut &bytes[..], &mut output).map_err(|e| {
+                    if output.bytes.len() as u64 > max_size {
+                        fail(too_large(max_size))
+                    } else {
+                        fail(e.to_string())
+                    }
+                })?;
+                output.bytes
+            }
+            Packing::Zstd => {
+                let mut decoder =
+                    ruzstd::StreamingDecoder::new(&bytes[..]).map_err(|e| fail(e.to_string()))?;
+                read_all(&mut decoder, max_size).map_err(fail)?
+            }
+            Packing::Bzip2 => {
+                let mut decoder = bzip2_rs::DecoderReader::new(&bytes[..]);
+                read_all(&mut decoder, max_size).map_err(fail)?
+            }
+            Packing::Zip => read_zip_member(&bytes, member.take(), max_size).map_err(fail)?,
+            Packing::Tar => read_tar_member(&bytes, member.take(), max_size).map_err(fail)?,
+        };
+    }
+    Ok(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    })
+}
+
+// Everything `reader` yields, failing once it goes past `max_size`
+fn read_all(reader: &mut impl Read, max_size: u64) -> std::result::Result<Vec<u8>, String> {
+    let mut output = vec![];
+    reader
+        .take(max_size + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| e.to_string())?;
+    if output.len() as u64 > max_size {
+        return Err(too_large(max_size));
+    }
+    Ok(output)
+}
+
+fn too_large(max_size: u64) -> String {
+    format!("unpacks to more than {} bytes", max_size)
+}
+
+// The xz decoder writes its output, it's cut off past `max_size`
+struct LimitedWriter {
+    bytes: Vec<u8>,
+    max_size: u64,
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let room = (self.max_size + 1).saturating_sub(self.bytes.len() as u64) as usize;
+        if room == 0 {
+            return Err(std::io::Error::other("output limit reached"));
+        }
+        let len = buf.len().min(room);
+        self.bytes.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// `./a/b.txt` and `a/b.txt` are the same member
+fn normalize(path: &str) -> &str {
+    path.trim_start_matches("./")
+}
+
+// The file to read out of an archive holding `files`
+fn choose_member(files: &[String], member: Option<&str>) -> std::result::Result<String, String> {
+    match member {
+        Some(member) => files
+            .iter()
+            .find(|file| normalize(file) == normalize(member))
+            .cloned()
+            .ok_or_else(|| format!("no member {}, it has {}", member, list(files))),
+        None => match files {
+            [file] => Ok(file.clone()),
+            _ => Err(format!(
+                "set `member` to the file to read, it has {}",
+                list(files)
+            )),
+        },
+    }
+}
+
+fn list(files: &[String]) -> String {
+    match files.len() {
+        0 => "no files".to_string(),
+        1..=10 => files.join(", "),
+        n => format!("{} and {} more", files[..10].join(", "), n - 10),
+    }
+}
+
+fn read_zip_member(
+    bytes: &[u8],
+    member: Option<&str>,
+    max_size: u64,
+) -> std::result::Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    let file = choose_member(&files, member)?;
+    let mut file = archive.by_name(&file).map_err(|e| e.to_string())?;
+    read_all(&mut file, max_size)
+}
+
+fn read_tar_member(
+    bytes: &[u8],
+    member: Option<&str>,
+    max_size: u64,
+) -> std::result::Result<Vec<u8>, String> {
+    let mut files = vec![];
+    let mut archive = tar::Archive::new(bytes);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.header().entry_type().is_file() {
+            let path = entry.path().map_err(|e| e.to_string())?;
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    let file = choose_member(&files, member)?;
+    // entries can only be read in order, so the archive is walked again
+    let mut archive = tar::Archive::new(bytes);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if entry.header().entry_type().is_file()
+            && entry.path().map_err(|e| e.to_string())?.to_string_lossy() == file
+        {
+            return read_all(&mut entry, max_size);
+        }
+    }
+    unreachable!("{} was listed in the archive", file)
+}
+
+#[test]
+fn test_unpack() {
+    let text = "||ads.example^\n";
+    let gzip = {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    };
+    assert_eq!(Packing::detect(&gzip, "rules.txt"), Packing::Gzip);
+    assert_eq!(unpack("rules.txt.gz", gzip.clone(), None).unwrap(), text);
+    let mut xz = vec![];
+    lzma_rs::xz_compress(&mut text.as_bytes(), &mut xz).unwrap();
+    assert_eq!(unpack("rules.txt.xz", xz, None).unwrap(), text);
+    assert_eq!(
+        unpack("rules.txt", text.as_bytes().to_vec(), None).unwrap(),
+        text
+    );
+
+    // rules.tar.gz holding lists/ads.txt and README
+    let tar = {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in [("./lists/ads.txt", text), ("README", "readme")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    };
+    let tar_gz = {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        encoder.finish().unwrap()
+    };
+    assert_eq!(
+        unpack("rules.tar.gz", tar_gz.clone(), Some("lists/ads.txt")).unwrap(),
+        text
+    );
+    let error = unpack("rules.tar.gz", tar_gz, None).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "rules.tar.gz: tar: set `member` to the file to read, it has lists/ads.txt, README"
+    );
+
+    let zip = {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .start_file("ads.txt.gz", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(&gzip).unwrap();
+        writer.finish().unwrap().into_inner()
+    };
+    assert_eq!(unpack("rules.zip", zip.clone(), None).unwrap(), text);
+    assert!(unpack("rules.zip", zip, Some("missing.txt")).is_err());
+
+    // gzip in gzip, one layer more than allowed
+    let gzip_of = |bytes: &[u8]| {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    };
+    let mut nested = text.as_bytes().to_vec();
+    for _ in 0..MAX_LAYERS {
+        nested = gzip_of(&nested);
+    }
+    assert_eq!(unpack("rules.gz", nested.clone(), None).unwrap(), text);
+    let error = unpack("rules.gz", gzip_of(&nested), None).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "rules.gz: gzip: packed more than 4 layers deep"
+    );
+
+    // a small file unpacking to more than the limit
+    let bomb = gzip_of(&[b'a'; 4096]);
+    let error = unpack_limited("rules.gz", bomb.clone(), None, 1024).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "rules.gz: gzip: unpacks to more than 1024 bytes"
+    );
+    let mut xz_bomb = vec![];
+    lzma_rs::xz_compress(&mut &[b'a'; 4096][..], &mut xz_bomb).unwrap();
+    let error = unpack_limited("rules.xz", xz_bomb, None, 1024).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "rules.xz: xz: unpacks to more than 1024 bytes"
+    );
+    assert_eq!(
+        unpack_limited("rules.gz", bomb, None, 4096).unwrap().len(),
+        4096
+    );
+}
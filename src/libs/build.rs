@@ -0,0 +1,82 @@
+use super::{
+    config::Config,
+    download::SourceContent,
+    error::Result,
+    lock::Lock,
+    output::{write_provenance_map, write_rules, OutputHeader},
+    report::{write_merge_report, write_run_report},
+    rule::Rule,
+};
+
+pub struct BuildOutput {
+    pub accept_rules: Vec<Rule>,
+    pub reject_rules: Vec<Rule>,
+}
+
+// Merge the fetched sources and write every configured output. A dry run
+// only merges, nothing is written.
+pub fn build(config: &Config, sources: &[SourceContent], dry_run: bool) -> Result<BuildOutput> {
+    let mut merged_rules = vec![];
+    let mut merge_reports = vec![];
+    for accept_rule in [true, false] {
+        let rules = if config.merge_report_path.is_some() {
+            let (rules, report) = config.merge_rules_with_report(sources, accept_rule);
+            merge_reports.push(report);
+            rules
+        } else {
+            config.merge_rules(sources, accept_rule)
+        };
+        let path = if accept_rule {
+            &config.accept_rule_path
+        } else {
+            &config.reject_rule_path
+        };
+        if !dry_run {
+            let header = OutputHeader::new(
+                config
+                    .rule_src
+                    .iter()
+                    .zip(sources)
+                    .filter(|(rule_src, _)| rule_src.provides(accept_rule))
+                    .map(|(_, source)| source)
+                    .collect(),
+            );
+            write_rules(
+                path,
+                &rules,
+                config.output_header.then_some(&header),
+                config.provenance_comments,
+            )?;
+        }
+        merged_rules.push(rules);
+    }
+    let reject_rules = merged_rules.pop().unwrap();
+    let accept_rules = merged_rules.pop().unwrap();
+    if dry_run {
+        return Ok(BuildOutput {
+            accept_rules,
+            reject_rules,
+        });
+    }
+
+    if let Some(path) = &config.provenance_map_path {
+        write_provenance_map(path, &accept_rules, &reject_rules)?;
+    }
+    if let Some(path) = &config.merge_report_path {
+        write_merge_report(path, &merge_reports[0], &merge_reports[1])?;
+    }
+    if let Some(path) = &config.run_report_path {
+        write_run_report(path, &config.rule_src, sources)?;
+    }
+    if let Some(geosite) = &config.geosite {
+        geosite.write(&accept_rules, &reject_rules)?;
+    }
+    // last, so that the lock only describes builds written in full
+    if let Some(path) = &config.lock_path {
+        Lock::new(&config.rule_src, sources).save(path)?;
+    }
+    Ok(BuildOutput {
+        accept_rules,
+        reject_rules,
+    })
+}
@@ -0,0 +1,105 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    download::SourceContent,
+    error::{Error, Result},
+};
+
+// On-disk copies of downloaded sources:
+//     <cache_dir>/index/<sha256 of location>.json   latest copy of a source
+//     <cache_dir>/blobs/<sha256 of content>         content addressed copies
+pub struct Cache {
+    dir: PathBuf,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    location: String,
+    fetched_at: u64, // seconds since UNIX epoch
+    sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    served_by: Option<String>,
+}
+
+impl Cache {
+    pub fn new(dir: &str) -> Self {
+        Cache {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    // Latest cached copy of the source at `location`
+    pub fn load(&self, location: &str) -> Option<SourceContent> {
+        let index = std::fs::read_to_string(self.index_path(location)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&index).ok()?;
+        let content = std::fs::read_to_string(self.blob_path(&entry.sha256)).ok()?;
+        Some(SourceContent {
+            location: entry.location,
+            fetched_at: SystemTime::UNIX_EPOCH + Duration::from_secs(entry.fetched_at),
+            sha256: entry.sha256,
+            content,
+            served_by: entry.served_by,
+            parts: vec![],
+        })
+    }
+
+    // Cached content with the SHA-256 `sha256`, whichever source it belongs to
+    pub fn load_blob(&self, sha256: &str) -> Option<String> {
+        std::fs::read_to_string(self.blob_path(sha256)).ok()
+    }
+
+    pub fn store(&self, source: &SourceContent) -> Result<()> {
+        for dir in ["index", "blobs"] {
+            let dir = self.dir.join(dir);
+            std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir.to_string_lossy(), e))?;
+        }
+        let entry = CacheEntry {
+            location: source.location.clone(),
+            fetched_at: source
+                .fetched_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            sha256: source.sha256.clone(),
+            served_by: source.served_by.clone(),
+        };
+        let blob_path = self.blob_path(&source.sha256);
+        std::fs::write(&blob_path, &source.content)
+            .map_err(|e| Error::io(&blob_path.to_string_lossy(), e))?;
+        let index_path = self.index_path(&source.location);
+        std::fs::write(&index_path, serde_json::to_string_pretty(&entry).unwrap())
+            .map_err(|e| Error::io(&index_path.to_string_lossy(), e))
+    }
+
+    fn index_path(&self, location: &str) -> PathBuf {
+        let key = format!("{:x}", Sha256::digest(location.as_bytes()));
+        self.dir.join("index").join(format!("{}.json", key))
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+}
+
+#[test]
+fn test_cache() {
+    let dir = std::env::temp_dir().join(format!("easy_adblock_cache_{}", std::process::id()));
+    let cache = Cache::new(dir.to_str().unwrap());
+    let location = "https://example.com/rules.txt";
+    assert!(cache.load(location).is_none());
+
+    let source = SourceContent::new(location.to_string(), "||ads.com^\n".to_string());
+    cache.store(&source).unwrap();
+    let cached = cache.load(location).unwrap();
+    assert_eq!(cached.location, source.location);
+    assert_eq!(cached.sha256, source.sha256);
+    assert_eq!(cached.content, source.content);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1,167 +1,444 @@
-use crate::libs::request::{RequestMethod, RequestStructure};
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-
-lazy_static!(
-    static ref HTTP_UA: String = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3".to_string();
-);
+use crate::libs::request::{
+    get_from_mirrors, HttpClient, HttpOptions, MirrorStrategy, SourceHttpOptions,
+};
+use log::warn;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{io::Write, sync::Arc};
 
 use super::{
-    download::Update,
-    rule::{Rule, RuleType},
+    cache::Cache,
+    download::{file_name, read_files, RawSource, SourceContent, Update},
+    error::{Error, Result},
+    format::RuleFormat,
+    geosite::GeositeOutput,
+    integrity::{Integrity, OnFailure},
+    lock::{Lock, DEFAULT_LOCK_PATH},
+    report::MergeReport,
+    rule::{Provenance, Rule},
+    tools::{merge_and_remove_duplicates_with_order, merge_with_report, OutputOrder},
 };
 
 #[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub rule_src: Vec<RuleSrc>,
     pub accept_rule_path: String,
     pub reject_rule_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geosite: Option<GeositeOutput>,
+    #[serde(default)]
+    pub output_header: bool,
+    #[serde(default)]
+    pub output_order: OutputOrder,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_dir: Option<String>, // copies of remote sources for offline builds
+    #[serde(default)]
+    pub offline: bool, // remote sources only come from the cache or `vendor_dir`
+    #[serde(default)]
+    pub provenance_comments: bool, // append `# source, ...` to every output rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance_map_path: Option<String>, // JSON map of rule -> sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge_report_path: Option<String>, // rules eliminated by the merge, JSON if `*.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_report_path: Option<String>, // JSON summary of where every source came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_path: Option<String>, // every build records its sources here, see `Lock`
+    #[serde(default)]
+    pub http: HttpOptions,
 }
 
+// A rule source as written in the config:
+//     {"type": "adguard", "url": "https://...", "direction": "both"}
+//     {"type": "mosdns_file", "path": "./allow.txt", "direction": "allow"}
+//     {"type": "files", "path": "/etc/adblock/custom.d/*.txt", "format": "pure"}
+// The positional form of earlier versions is still accepted, see `LegacyRuleSrc`.
 #[derive(Deserialize, Serialize)]
+#[serde(from = "RuleSrcConfig")]
 pub struct RuleSrc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
     pub src_type: RuleSrcType,
+    pub direction: Direction,
+    pub enabled: bool,
     pub auto_update: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<SourceHttpOptions>, // remote sources only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<Integrity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>, // file to read out of a zip or tar source
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum RuleSrcType {
+    MosdnsFile {
+        path: String,
+    },
+    PureFile {
+        path: String,
+    },
+    #[serde(rename = "adguard")]
+    AdguardHomeRule {
+        url: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mirrors: Vec<String>, // copies of `url`, such as jsDelivr or a self-hosted one
+        #[serde(default, skip_serializing_if = "MirrorStrategy::is_ordered")]
+        mirror_strategy: MirrorStrategy,
+    },
+    Geosite {
+        url: String, // geosite.dat update URL
+        category: String,
+    },
+    Files {
+        path: String, // directory or glob pattern, read again on every refresh
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<RuleFormat>, // detected per file if not set
+    },
+    #[default]
+    #[serde(skip_deserializing)]
+    Unknown,
+}
+
+// Which rules a source contributes, `both` only makes sense for formats
+// marking allow rules themselves, such as AdGuard's `@@||example.com^`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Allow,
+    Block,
+    Both,
+}
+
+impl Direction {
+    pub fn from_accept_rule(accept_rule: bool) -> Self {
+        if accept_rule {
+            Direction::Allow
+        } else {
+            Direction::Block
+        }
+    }
+
+    pub fn includes(&self, want_accept_rule: bool) -> bool {
+        match self {
+            Direction::Allow => want_accept_rule,
+            Direction::Block => !want_accept_rule,
+            Direction::Both => true,
+        }
+    }
+}
+
+enum RuleSrcConfig {
+    Legacy(LegacyRuleSrc),
+    Tagged(Box<TaggedRuleSrc>),
+}
+
+// Every key of the tagged form, `flatten` rules out `deny_unknown_fields`
+const RULE_SRC_KEYS: &[&str] = &[
+    "name",
+    "type",
+    "path",
+    "url",
+    "mirrors",
+    "mirror_strategy",
+    "category",
+    "format",
+    "direction",
+    "enabled",
+    "auto_update",
+    "http",
+    "integrity",
+    "member",
+];
+
+impl<'de> Deserialize<'de> for RuleSrcConfig {
+    // Pick the form by its keys, an untagged enum would hide why neither matched
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("src_type").is_some() {
+            return LegacyRuleSrc::deserialize(value)
+                .map(RuleSrcConfig::Legacy)
+                .map_err(de::Error::custom);
+        }
+        if let Some(map) = value.as_object() {
+            if let Some(key) = map
+                .keys()
+                .find(|key| !RULE_SRC_KEYS.contains(&key.as_str()))
+            {
+                return Err(de::Error::unknown_field(key, RULE_SRC_KEYS));
+            }
+        }
+        TaggedRuleSrc::deserialize(value)
+            .map(|tagged| RuleSrcConfig::Tagged(Box::new(tagged)))
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+struct TaggedRuleSrc {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(flatten)]
+    src_type: RuleSrcType,
+    #[serde(default)]
+    direction: Option<Direction>, // AdGuard lists default to both, others to block
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default = "default_true")]
+    auto_update: bool,
+    #[serde(default)]
+    http: Option<SourceHttpOptions>,
+    #[serde(default)]
+    integrity: Option<Integrity>,
+    #[serde(default)]
+    member: Option<String>,
+}
+
+// `{"src_type": {"MosdnsFile": ["/path", false]}, "auto_update": true}`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyRuleSrc {
+    src_type: LegacyRuleSrcType,
+    auto_update: bool,
+}
+
+#[derive(Deserialize)]
+enum LegacyRuleSrcType {
     MosdnsFile(String, bool),      // file_path, accept/reject(true/false)
     PureFile(String, bool),        // file_path, accept/reject(true/false)
-    AdguardHomeRule(String),       // URL, accept/reject(true/false)
+    AdguardHomeRule(String),       // URL
     Geosite(String, String, bool), // geosite_update_url, geosite_category, accept/reject(true/false)
     Unknown,
 }
 
-impl Update for RuleSrcType {
-    async fn get(&self, want_accept_rule: bool) -> Result<Vec<Rule>, String> {
-        match self {
-            RuleSrcType::MosdnsFile(file_path, accept_rule) => {
-                if want_accept_rule != *accept_rule {
-                    return Ok(vec![]);
-                }
-                let mut rules = vec![];
-                // read file
-                let file = std::fs::File::open(file_path);
-                if file.is_err() {
-                    return Err("Failed to open file".to_string());
-                }
-                let file = file.unwrap();
-                let mut reader = std::io::BufReader::new(file);
-                let mut buf = String::new();
-                reader.read_to_string(&mut buf).unwrap();
-                for line in buf.lines() {
-                    let rule_string = line.trim().split_once(':').unwrap();
-                    let rule_type = match rule_string.0.trim() {
-                        "domain" => RuleType::Domain,
-                        "full" => RuleType::Full,
-                        _ => continue,
-                    };
-                    let rule_content = rule_string.1.trim().to_string();
-                    rules.push(Rule::new(rule_type, rule_content));
-                }
+fn default_true() -> bool {
+    true
+}
 
-                Ok(rules)
-            }
-            RuleSrcType::PureFile(path, accept_rule) => {
-                if want_accept_rule != *accept_rule {
-                    return Ok(vec![]);
-                }
-                let mut rules = vec![];
-                // read file
-                let file = std::fs::File::open(path);
-                if file.is_err() {
-                    return Err("Failed to open file".to_string());
-                }
-                let file = file.unwrap();
-                let mut reader = std::io::BufReader::new(file);
-                let mut buf = String::new();
-                reader.read_to_string(&mut buf).unwrap();
-                for line in buf.lines() {
-                    let rule_type = RuleType::Domain;
-                    let rule_content = line.trim().to_string();
-                    rules.push(Rule::new(rule_type, rule_content));
+impl From<RuleSrcConfig> for RuleSrc {
+    fn from(config: RuleSrcConfig) -> Self {
+        match config {
+            RuleSrcConfig::Tagged(tagged) => {
+                let tagged = *tagged;
+                let direction = tagged.direction.unwrap_or(match tagged.src_type {
+                    RuleSrcType::AdguardHomeRule { .. } => Direction::Both,
+                    _ => Direction::Block,
+                });
+                RuleSrc {
+                    name: tagged.name,
+                    src_type: tagged.src_type,
+                    direction,
+                    enabled: tagged.enabled,
+                    auto_update: tagged.auto_update,
+                    http: tagged.http,
+                    integrity: tagged.integrity,
+                    member: tagged.member,
                 }
-
-                Ok(rules)
             }
-            RuleSrcType::AdguardHomeRule(url) => {
-                let request_structure = RequestStructure::new(
-                    RequestMethod::GET,
-                    url.to_string(),
-                    "".to_owned(),
-                    None,
-                    None,
-                    Some(HTTP_UA.to_owned()),
-                    None,
-                );
-                // let response = request_structure.execute().await;
-                // if response.is_err() {
-                //     return Err("Failed to get AdguardHomeRule".to_string());
-                // }
-                let mut retry = 3;
-                let response = loop {
-                    let response = request_structure.execute().await;
-                    if response.is_err() {
-                        if retry > 0 {
-                            retry -= 1;
-                            continue;
-                        } else {
-                            return Err("Failed to get AdguardHomeRule".to_string());
-                        }
-                    }
-                    break response;
+            RuleSrcConfig::Legacy(legacy) => {
+                let (src_type, direction) = match legacy.src_type {
+                    LegacyRuleSrcType::MosdnsFile(path, accept_rule) => (
+                        RuleSrcType::MosdnsFile { path },
+                        Direction::from_accept_rule(accept_rule),
+                    ),
+                    LegacyRuleSrcType::PureFile(path, accept_rule) => (
+                        RuleSrcType::PureFile { path },
+                        Direction::from_accept_rule(accept_rule),
+                    ),
+                    LegacyRuleSrcType::AdguardHomeRule(url) => (
+                        RuleSrcType::AdguardHomeRule {
+                            url,
+                            mirrors: vec![],
+                            mirror_strategy: MirrorStrategy::Ordered,
+                        },
+                        Direction::Both,
+                    ),
+                    LegacyRuleSrcType::Geosite(url, category, accept_rule) => (
+                        RuleSrcType::Geosite { url, category },
+                        Direction::from_accept_rule(accept_rule),
+                    ),
+                    LegacyRuleSrcType::Unknown => (RuleSrcType::Unknown, Direction::Block),
                 };
-                let response = response.unwrap();
-                let content = response.2;
-                let mut rules = vec![];
-                if want_accept_rule {
-                    for line in content.lines() {
-                        if line.starts_with("@@||") && line.ends_with("^") {
-                            // println!("{}", line);
-                            let rule_content =
-                                line.trim_start_matches("@@||").trim_end_matches("^");
-                            rules.push(Rule::new(RuleType::Domain, rule_content.to_string()));
-                        }
-                    }
-                } else {
-                    for line in content.lines() {
-                        if line.starts_with("||") && line.ends_with("^") {
-                            // println!("{}", line);
-                            let rule_content = line.trim_start_matches("||").trim_end_matches("^");
-                            rules.push(Rule::new(RuleType::Domain, rule_content.to_string()));
-                        }
-                    }
-                }
-                Ok(rules)
+                RuleSrc::new(src_type, direction, legacy.auto_update)
             }
-            RuleSrcType::Geosite(_geosite_update_url, _geosite_category, _accept_rule) => {
-                todo!("Geosite is not implemented yet");
+        }
+    }
+}
+
+// A single source on its own, `get` keeps only the rules of the wanted
+// direction as the merge does
+impl Update for RuleSrc {
+    async fn fetch(&self, client: &HttpClient) -> Result<SourceContent> {
+        let client = match &self.http {
+            Some(http) => &client.with_options(client.options().merged(http)),
+            None => client,
+        };
+        self.src_type
+            .fetch_unpacked(client, self.member.as_deref())
+            .await
+    }
+
+    // Without the parts of the source the files are detected as a whole,
+    // `parse_source` keeps the format of each one
+    fn parse(&self, content: &str, want_accept_rule: bool) -> Vec<Rule> {
+        if !self.provides(want_accept_rule) {
+            return vec![];
+        }
+        match (&self.src_type, self.src_type.format()) {
+            (_, Some(format)) => format.parse(content, want_accept_rule),
+            (RuleSrcType::Files { .. }, None) => {
+                RuleFormat::detect(content).parse(content, want_accept_rule)
             }
-            RuleSrcType::Unknown => Err("Unknown RuleSrcType".to_string()),
+            (_, None) => vec![],
+        }
+    }
+}
+
+impl RuleSrcType {
+    // The text of the source, `member` selects the file of an archive
+    pub async fn fetch_unpacked(
+        &self,
+        client: &HttpClient,
+        member: Option<&str>,
+    ) -> Result<SourceContent> {
+        match self {
+            RuleSrcType::Files { path, format } => read_files(path, *format),
+            _ => self.fetch_raw(client).await?.unpack(member),
+        }
+    }
+
+    // The bytes of the source as they were read or downloaded
+    pub async fn fetch_raw(&self, client: &HttpClient) -> Result<RawSource> {
+        match self {
+            RuleSrcType::MosdnsFile { path } | RuleSrcType::PureFile { path } => Ok(RawSource {
+                location: path.to_string(),
+                served_by: None,
+                bytes: std::fs::read(path).map_err(|e| Error::io(path, e))?,
+            }),
+            RuleSrcType::AdguardHomeRule {
+                url,
+                mirrors,
+                mirror_strategy,
+            } => {
+                // proxy, headers, auth and the like come from the options of the client
+                let urls: Vec<String> = std::iter::once(url).chain(mirrors).cloned().collect();
+                let (served_by, bytes) = get_from_mirrors(client, &urls, *mirror_strategy).await?;
+                // the source keeps the location of `url`, whichever mirror served it
+                Ok(RawSource {
+                    location: url.to_string(),
+                    served_by: (served_by != *url).then_some(served_by),
+                    bytes,
+                })
+            }
+            RuleSrcType::Geosite { .. } => Err(Error::Config(
+                "geosite sources are not supported yet".to_string(),
+            )),
+            RuleSrcType::Files { path, .. } => Err(Error::Config(format!(
+                "{} is read file by file, see `read_files`",
+                path
+            ))),
+            RuleSrcType::Unknown => Err(Error::Config("unknown source type".to_string())),
+        }
+    }
+
+    // File path, pattern or URL the source is read from
+    pub fn location(&self) -> String {
+        match self {
+            RuleSrcType::MosdnsFile { path }
+            | RuleSrcType::PureFile { path }
+            | RuleSrcType::Files { path, .. } => path.to_string(),
+            RuleSrcType::AdguardHomeRule { url, .. } | RuleSrcType::Geosite { url, .. } => {
+                url.to_string()
+            }
+            RuleSrcType::Unknown => "unknown".to_string(),
+        }
+    }
+
+    // Whether the source is downloaded, only those are kept in the cache
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self,
+            RuleSrcType::AdguardHomeRule { .. } | RuleSrcType::Geosite { .. }
+        )
+    }
+
+    // The rules of a fetched source, each file of a `files` source in its own format
+    pub fn parse_source(&self, source: &SourceContent, want_accept_rule: bool) -> Vec<Rule> {
+        source
+            .lines(self.format())
+            .filter_map(|line| line.parse(want_accept_rule))
+            .collect()
+    }
+
+    pub fn parse_with_provenance(
+        &self,
+        source: &SourceContent,
+        want_accept_rule: bool,
+    ) -> Vec<Rule> {
+        // shared by the rules of a file, the lines of a file come in a row
+        let mut source_id: Arc<str> = source.location.as_str().into();
+        source
+            .lines(self.format())
+            .filter_map(|line| {
+                let rule = line.parse(want_accept_rule)?;
+                if *source_id != *line.location {
+                    source_id = line.location.into();
+                }
+                Some(rule.with_provenance(Provenance {
+                    source: source_id.clone(),
+                    line_number: line.line_number,
+                    line: line.line.to_string(),
+                }))
+            })
+            .collect()
+    }
+
+    // `None` for `files` sources without a format, see `SourceContent::lines`
+    pub fn format(&self) -> Option<RuleFormat> {
+        match self {
+            RuleSrcType::MosdnsFile { .. } => Some(RuleFormat::Mosdns),
+            RuleSrcType::PureFile { .. } => Some(RuleFormat::Pure),
+            RuleSrcType::AdguardHomeRule { .. } => Some(RuleFormat::Adguard),
+            RuleSrcType::Files { format, .. } => *format,
+            RuleSrcType::Geosite { .. } | RuleSrcType::Unknown => None,
         }
     }
 }
 
 impl RuleSrc {
-    pub fn new(src_type: RuleSrcType, auto_update: bool) -> Self {
+    pub fn new(src_type: RuleSrcType, direction: Direction, auto_update: bool) -> Self {
         RuleSrc {
+            name: None,
             src_type,
+            direction,
+            enabled: true,
             auto_update,
+            http: None,
+            integrity: None,
+            member: None,
         }
     }
     pub fn from_mosdns_file(file_path: String, accept_rule: bool, auto_update: bool) -> Self {
-        RuleSrc {
-            src_type: RuleSrcType::MosdnsFile(file_path, accept_rule),
+        RuleSrc::new(
+            RuleSrcType::MosdnsFile { path: file_path },
+            Direction::from_accept_rule(accept_rule),
             auto_update,
-        }
+        )
     }
     pub fn from_adguard_home_rule(url: String, auto_update: bool) -> Self {
-        RuleSrc {
-            src_type: RuleSrcType::AdguardHomeRule(url),
+        RuleSrc::new(
+            RuleSrcType::AdguardHomeRule {
+                url,
+                mirrors: vec![],
+                mirror_strategy: MirrorStrategy::Ordered,
+            },
+            Direction::Both,
             auto_update,
-        }
+        )
     }
     pub fn from_geosite(
         geosite_update_url: String,
@@ -169,16 +446,21 @@ impl RuleSrc {
         accept_rule: bool,
         auto_update: bool,
     ) -> Self {
-        RuleSrc {
-            src_type: RuleSrcType::Geosite(geosite_update_url, geosite_category, accept_rule),
+        RuleSrc::new(
+            RuleSrcType::Geosite {
+                url: geosite_update_url,
+                category: geosite_category,
+            },
+            Direction::from_accept_rule(accept_rule),
             auto_update,
-        }
+        )
     }
-}
 
-impl Default for RuleSrcType {
-    fn default() -> Self {
-        RuleSrcType::Unknown
+    // Whether this source contributes accept(true) or reject(false) rules
+    pub fn provides(&self, want_accept_rule: bool) -> bool {
+        self.enabled
+            && self.direction.includes(want_accept_rule)
+            && !matches!(self.src_type, RuleSrcType::Unknown)
     }
 }
 
@@ -188,30 +470,386 @@ impl Config {
             rule_src,
             accept_rule_path: "./accept.txt".to_string(),
             reject_rule_path: "./reject.txt".to_string(),
+            geosite: None,
+            output_header: false,
+            output_order: OutputOrder::default(),
+            cache_dir: None,
+            vendor_dir: None,
+            offline: false,
+            provenance_comments: false,
+            provenance_map_path: None,
+            merge_report_path: None,
+            run_report_path: None,
+            lock_path: None,
+            http: HttpOptions::default(),
         }
     }
     pub fn add(&mut self, rule_src: RuleSrc) {
         self.rule_src.push(rule_src);
     }
-    pub fn load(file_path: &str) -> Result<Self, String> {
-        let file = std::fs::File::open(file_path);
-        if file.is_err() {
-            return Err("Failed to open file".to_string());
+    // One client for every remote source, see `HttpOptions`
+    pub fn http_client(&self) -> Result<HttpClient> {
+        HttpClient::new(&self.http)
+    }
+
+    // Fetch every rule source, remote sources are stored in the cache. With
+    // `prefer_cache` a cached copy is used instead of downloading again.
+    pub async fn fetch_sources(
+        &self,
+        client: &HttpClient,
+        prefer_cache: bool,
+    ) -> Result<Vec<SourceContent>> {
+        let mut sources = vec![];
+        for rule_src in &self.rule_src {
+            sources.push(self.fetch_source(client, rule_src, prefer_cache).await?);
         }
-        let file = file.unwrap();
-        let reader = std::io::BufReader::new(file);
-        let config: Config = serde_json::from_reader(reader).unwrap();
-        Ok(config)
+        Ok(sources)
     }
-    pub fn save(&self, file_path: &str) -> Result<(), String> {
-        let file = std::fs::File::create(file_path);
-        if file.is_err() {
-            return Err("Failed to create file".to_string());
+
+    // The sources recorded in `lock` without downloading anything: remote
+    // sources come from the cache, files are read and must still match
+    pub async fn locked_sources(
+        &self,
+        client: &HttpClient,
+        lock: &Lock,
+    ) -> Result<Vec<SourceContent>> {
+        let mut sources = vec![];
+        for rule_src in &self.rule_src {
+            let location = rule_src.src_type.location();
+            if !rule_src.enabled {
+                sources.push(SourceContent::new(location, String::new()));
+                continue;
+            }
+            let locked = lock.find(&location).ok_or_else(|| {
+                Error::Config(format!(
+                    "{} is not in the lock, update the lock first",
+                    location
+                ))
+            })?;
+            let source = if rule_src.src_type.is_remote() {
+                let content = self
+                    .cache_dir
+                    .as_ref()
+                    .and_then(|cache_dir| Cache::new(cache_dir).load_blob(&locked.sha256))
+                    .ok_or_else(|| {
+                        Error::Config(format!(
+                            "{}: no cached copy with SHA-256 {}",
+                            location, locked.sha256
+                        ))
+                    })?;
+                SourceContent::new(location, content)
+            } else {
+                rule_src
+                    .src_type
+                    .fetch_unpacked(client, rule_src.member.as_deref())
+                    .await?
+            };
+            sources.push(locked.restore(source)?);
         }
-        let mut file = file.unwrap();
-        let json_config = serde_json::to_string_pretty(self).unwrap();
-        file.write_all(json_config.as_bytes()).unwrap();
-        Ok(())
+        Ok(sources)
+    }
+
+    // Whether any enabled source is downloaded
+    pub fn has_remote_sources(&self) -> bool {
+        self.rule_src
+            .iter()
+            .any(|rule_src| rule_src.enabled && rule_src.src_type.is_remote())
+    }
+
+    pub fn lock_path(&self) -> &str {
+        self.lock_path.as_deref().unwrap_or(DEFAULT_LOCK_PATH)
+    }
+
+    // Fetch the sources with `auto_update` again, keep the others. `files`
+    // sources are always listed again, to pick up files added or removed.
+    pub async fn refresh_sources(
+        &self,
+        client: &HttpClient,
+        previous: Vec<SourceContent>,
+    ) -> Result<Vec<SourceContent>> {
+        let mut sources = vec![];
+        for (rule_src, source) in self.rule_src.iter().zip(previous) {
+            if rule_src.auto_update || matches!(rule_src.src_type, RuleSrcType::Files { .. }) {
+                sources.push(self.fetch_source(client, rule_src, false).await?);
+            } else {
+                sources.push(source);
+            }
+        }
+        Ok(sources)
+    }
+
+    async fn fetch_source(
+        &self,
+        client: &HttpClient,
+        rule_src: &RuleSrc,
+        prefer_cache: bool,
+    ) -> Result<SourceContent> {
+        let location = rule_src.src_type.location();
+        // disabled sources are kept as empty ones so that `sources` lines up with `rule_src`
+        if !rule_src.enabled {
+            return Ok(SourceContent::new(location, String::new()));
+        }
+        if self.offline && rule_src.src_type.is_remote() {
+            return self.offline_source(rule_src);
+        }
+        if let RuleSrcType::Files { path, format } = &rule_src.src_type {
+            return read_files(path, *format);
+        }
+        let client = match &rule_src.http {
+            Some(http) => &client.with_options(client.options().merged(http)),
+            None => client,
+        };
+        let cache = match &self.cache_dir {
+            Some(cache_dir) if rule_src.src_type.is_remote() => Some(Cache::new(cache_dir)),
+            _ => None,
+        };
+        if prefer_cache {
+            if let Some(source) = cache.as_ref().and_then(|cache| cache.load(&location)) {
+                return Ok(source);
+            }
+        }
+        let raw = rule_src.src_type.fetch_raw(client).await?;
+        // only verified content makes it into the cache
+        if let Some(integrity) = &rule_src.integrity {
+            if let Err(e) = integrity.verify(client, &raw).await {
+                return match integrity.on_failure {
+                    OnFailure::Fail => Err(e),
+                    OnFailure::Cache => match cache.and_then(|cache| cache.load(&location)) {
+                        Some(cached) => {
+                            warn!(
+                                "{}, using the copy fetched at {}",
+                                e.chain(),
+                                humantime::format_rfc3339_seconds(cached.fetched_at)
+                            );
+                            Ok(cached)
+                        }
+                        None => Err(e),
+                    },
+                    OnFailure::Skip => {
+                        warn!("{}, the source is left out", e.chain());
+                        Ok(SourceContent::new(location, String::new()))
+                    }
+                };
+            }
+        }
+        let source = raw.unpack(rule_src.member.as_deref())?;
+        if let Some(cache) = cache {
+            cache.store(&source)?;
+        }
+        Ok(source)
+    }
+
+    // A remote source without downloading it: the latest cached copy, else
+    // `<vendor_dir>/<name>` or `<vendor_dir>/<file name of the URL>`
+    fn offline_source(&self, rule_src: &RuleSrc) -> Result<SourceContent> {
+        let location = rule_src.src_type.location();
+        if let Some(cache_dir) = &self.cache_dir {
+            if let Some(source) = Cache::new(cache_dir).load(&location) {
+                return Ok(source);
+            }
+        }
+        let vendored = self.vendor_dir.as_ref().and_then(|vendor_dir| {
+            rule_src
+                .name
+                .iter()
+                .map(String::as_str)
+                .chain([file_name(&location)])
+                .map(|file| std::path::Path::new(vendor_dir).join(file))
+                .find(|path| path.is_file())
+        });
+        let Some(path) = vendored else {
+            return Err(Error::Config(format!(
+                "{}: no cached or vendored copy to build from offline",
+                location
+            )));
+        };
+        let path_str = path.to_string_lossy();
+        let raw = RawSource {
+            location,
+            served_by: Some(path_str.to_string()),
+            bytes: std::fs::read(&path).map_err(|e| Error::io(&path_str, e))?,
+        };
+        if let Some(integrity) = &rule_src.integrity {
+            integrity.verify_offline(&raw)?;
+        }
+        let mut source = raw.unpack(rule_src.member.as_deref())?;
+        if let Ok(modified) = path.metadata().and_then(|metadata| metadata.modified()) {
+            source.fetched_at = modified;
+        }
+        Ok(source)
+    }
+
+    // Merge the accept(true) or reject(false) rules of the fetched sources
+    pub fn merge_rules(&self, sources: &[SourceContent], accept_rule: bool) -> Vec<Rule> {
+        merge_and_remove_duplicates_with_order(
+            self.parse_sources(sources, accept_rule),
+            self.output_order,
+        )
+    }
+
+    pub fn merge_rules_with_report(
+        &self,
+        sources: &[SourceContent],
+        accept_rule: bool,
+    ) -> (Vec<Rule>, MergeReport) {
+        merge_with_report(self.parse_sources(sources, accept_rule), self.output_order)
+    }
+
+    fn parse_sources(&self, sources: &[SourceContent], accept_rule: bool) -> Vec<Vec<Rule>> {
+        let mut rules_vec = vec![];
+        for (rule_src, source) in self.rule_src.iter().zip(sources) {
+            if !rule_src.provides(accept_rule) {
+                continue;
+            }
+            rules_vec.push(if self.tracks_provenance() {
+                rule_src.src_type.parse_with_provenance(source, accept_rule)
+            } else {
+                rule_src.src_type.parse_source(source, accept_rule)
+            });
+        }
+        rules_vec
+    }
+
+    // Keeping the source line of every rule costs memory, only do it when
+    // an output needs it
+    pub fn tracks_provenance(&self) -> bool {
+        self.provenance_comments
+            || self.provenance_map_path.is_some()
+            || self.merge_report_path.is_some()
+    }
+
+    // JSON, YAML or TOML, picked by the extension of the file or, without
+    // a known one, by its content
+    pub fn load(file_path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(file_path).map_err(|e| Error::io(file_path, e))?;
+        let format =
+            ConfigFormat::from_path(file_path).unwrap_or_else(|| ConfigFormat::detect(&content));
+        format.parse(&content, file_path)
+    }
+
+    // Written as JSON unless the extension asks for YAML or TOML
+    pub fn save(&self, file_path: &str) -> Result<()> {
+        let content = self.render(file_path)?;
+        std::fs::File::create(file_path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|e| Error::io(file_path, e))
+    }
+
+    // The content `save` would write to `file_path`
+    pub fn render(&self, file_path: &str) -> Result<String> {
+        ConfigFormat::from_path(file_path)
+            .unwrap_or(ConfigFormat::Json)
+            .render(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(file_path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(file_path).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+
+    // JSON starts with `{`, TOML with a `key = value` or a `[table]` line,
+    // anything else is read as YAML
+    pub fn detect(content: &str) -> Self {
+        let first_line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        if first_line.starts_with('{') {
+            ConfigFormat::Json
+        } else if first_line.starts_with('[')
+            || first_line
+                .split_once('=')
+                .is_some_and(|(key, _)| !key.contains(':'))
+        {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Toml => "TOML",
+        }
+    }
+
+    // `location` names the config in errors, which carry the line and column
+    pub fn parse(&self, content: &str, location: &str) -> Result<Config> {
+        let (message, line, column) = match self {
+            ConfigFormat::Json => match serde_json::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => (e.to_string(), Some(e.line()), Some(e.column())),
+            },
+            ConfigFormat::Yaml => match serde_yaml::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => {
+                    let location = e.location();
+                    (
+                        e.to_string(),
+                        location.as_ref().map(|l| l.line()),
+                        location.as_ref().map(|l| l.column()),
+                    )
+                }
+            },
+            ConfigFormat::Toml => match toml::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => {
+                    let (line, column) = match e.span() {
+                        Some(span) => {
+                            let before = &content[..span.start];
+                            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                            (
+                                Some(before.matches('\n').count() + 1),
+                                Some(before[line_start..].chars().count() + 1),
+                            )
+                        }
+                        None => (None, None),
+                    };
+                    (e.message().to_string(), line, column)
+                }
+            },
+        };
+        // serde_json and serde_yaml append the position to the message
+        let message = match (line, column) {
+            (Some(line), Some(column)) => message
+                .trim_end_matches(&format!(" at line {} column {}", line, column))
+                .to_string(),
+            _ => message,
+        };
+        Err(Error::Parse {
+            location: location.to_string(),
+            line,
+            column,
+            message: format!("invalid {} config: {}", self.name(), message),
+        })
+    }
+
+    pub fn render(&self, config: &Config) -> Result<String> {
+        let content = match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::to_string(config).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(config).map_err(|e| e.to_string()),
+        };
+        content.map_err(|e| {
+            Error::Config(format!("Failed to serialize {} config: {}", self.name(), e))
+        })
     }
 }
 
@@ -228,7 +866,195 @@ fn tset_config() {
             .to_string(),
         true,
     ));
+    config.geosite = Some(GeositeOutput {
+        path: "geosite.dat".to_string(),
+        accept_category: "category-ads-allow".to_string(),
+        reject_category: "category-ads-merged".to_string(),
+    });
+
+    let dir = std::env::temp_dir().join(format!("easy_adblock_config_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["config.json", "config.yaml", "config.toml"] {
+        let path = dir.join(name);
+        let path = path.to_str().unwrap();
+        config.save(path).unwrap();
+        let loaded = Config::load(path).unwrap();
+        assert_eq!(loaded.rule_src.len(), 2);
+        assert_eq!(loaded.geosite.unwrap().path, "geosite.dat");
+
+        // without an extension the format is guessed from the content
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            ConfigFormat::detect(&content),
+            ConfigFormat::from_path(name).unwrap()
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_rule_src_schema() {
+    let legacy = r#"{
+        "rule_src": [
+            {"src_type": {"MosdnsFile": ["allow.txt", true]}, "auto_update": false},
+            {"src_type": {"AdguardHomeRule": "https://example.com/rules.txt"}, "auto_update": true}
+        ],
+        "accept_rule_path": "accept.txt",
+        "reject_rule_path": "reject.txt"
+    }"#;
+    let tagged = r#"{
+        "rule_src": [
+            {"type": "mosdns_file", "path": "allow.txt", "direction": "allow", "auto_update": false},
+            {"type": "adguard", "url": "https://example.com/rules.txt", "name": "example"},
+            {"type": "pure_file", "path": "block.txt", "enabled": false}
+        ],
+        "accept_rule_path": "accept.txt",
+        "reject_rule_path": "reject.txt"
+    }"#;
+    for content in [legacy, tagged] {
+        let config = ConfigFormat::Json.parse(content, "config.json").unwrap();
+        let allow = &config.rule_src[0];
+        assert!(matches!(&allow.src_type, RuleSrcType::MosdnsFile { path } if path == "allow.txt"));
+        assert_eq!(allow.direction, Direction::Allow);
+        assert!(!allow.auto_update);
+        let adguard = &config.rule_src[1];
+        assert_eq!(adguard.direction, Direction::Both);
+        assert!(adguard.enabled && adguard.auto_update);
+        assert!(adguard.provides(true) && adguard.provides(false));
+    }
+
+    let config = ConfigFormat::Json.parse(tagged, "config.json").unwrap();
+    assert_eq!(config.rule_src[1].name.as_deref(), Some("example"));
+    assert_eq!(config.rule_src[2].direction, Direction::Block);
+    assert!(!config.rule_src[2].provides(false));
+
+    // old files are rewritten in the new schema
+    let migrated = ConfigFormat::Json
+        .render(&ConfigFormat::Json.parse(legacy, "config.json").unwrap())
+        .unwrap();
+    assert!(migrated.contains(r#""type": "mosdns_file""#));
+    assert!(migrated.contains(r#""direction": "allow""#));
+    assert!(!migrated.contains("src_type"));
+    assert!(!migrated.contains("mirror"));
+
+    let mirrored = r#"{
+        "rule_src": [{
+            "type": "adguard",
+            "url": "https://example.com/rules.txt",
+            "mirrors": ["https://mirror.example.com/rules.txt"],
+            "mirror_strategy": "race"
+        }],
+        "accept_rule_path": "accept.txt",
+        "reject_rule_path": "reject.txt"
+    }"#;
+    let config = ConfigFormat::Json.parse(mirrored, "config.json").unwrap();
+    assert!(matches!(
+        &config.rule_src[0].src_type,
+        RuleSrcType::AdguardHomeRule { mirrors, mirror_strategy: MirrorStrategy::Race, .. }
+            if mirrors == &["https://mirror.example.com/rules.txt"]
+    ));
+}
+
+#[test]
+fn test_offline_source() {
+    let dir = std::env::temp_dir().join(format!("easy_adblock_offline_{}", std::process::id()));
+    let vendor_dir = dir.join("vendor");
+    std::fs::create_dir_all(&vendor_dir).unwrap();
+    std::fs::write(vendor_dir.join("rules.txt"), "||ads.com^\n").unwrap();
+    std::fs::write(vendor_dir.join("named"), "||named.com^\n").unwrap();
+
+    let mut config = Config::new(vec![
+        RuleSrc::from_adguard_home_rule("https://example.com/rules.txt".to_string(), true),
+        RuleSrc::from_adguard_home_rule("https://example.com/other.txt".to_string(), true),
+        RuleSrc::from_adguard_home_rule("https://example.com/missing.txt".to_string(), true),
+    ]);
+    config.rule_src[1].name = Some("named".to_string());
+    config.offline = true;
+    config.vendor_dir = Some(vendor_dir.to_str().unwrap().to_string());
+    config.cache_dir = Some(dir.join("cache").to_str().unwrap().to_string());
+
+    let vendored = config.offline_source(&config.rule_src[0]).unwrap();
+    assert_eq!(vendored.location, "https://example.com/rules.txt");
+    assert_eq!(vendored.content, "||ads.com^\n");
+    assert!(vendored.served_by.unwrap().ends_with("rules.txt"));
+    let named = config.offline_source(&config.rule_src[1]).unwrap();
+    assert_eq!(named.content, "||named.com^\n");
+    let error = config.offline_source(&config.rule_src[2]).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "https://example.com/missing.txt: no cached or vendored copy to build from offline"
+    );
+
+    // the cache comes first
+    let cached = SourceContent::new(
+        "https://example.com/rules.txt".to_string(),
+        "||cached.com^\n".to_string(),
+    );
+    Cache::new(config.cache_dir.as_ref().unwrap())
+        .store(&cached)
+        .unwrap();
+    let source = config.offline_source(&config.rule_src[0]).unwrap();
+    assert_eq!(source.content, cached.content);
+
+    config.rule_src[0].integrity = Some(Integrity {
+        sha256: Some("0".repeat(64)),
+        ..Integrity::default()
+    });
+    std::fs::remove_dir_all(dir.join("cache")).unwrap();
+    assert!(config.offline_source(&config.rule_src[0]).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_refresh_files() {
+    let dir = std::env::temp_dir().join(format!("easy_adblock_refresh_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "a.com\n").unwrap();
+    let mut files = RuleSrc::new(
+        RuleSrcType::Files {
+            path: dir.to_str().unwrap().to_string(),
+            format: None,
+        },
+        Direction::Block,
+        false,
+    );
+    files.name = Some("custom".to_string());
+    let config = Config::new(vec![files]);
+    let client = config.http_client().unwrap();
+
+    let sources = config.fetch_sources(&client, false).await.unwrap();
+    assert_eq!(sources[0].content, "a.com\n");
+    // listed again even without `auto_update`
+    std::fs::remove_file(dir.join("a.txt")).unwrap();
+    std::fs::write(dir.join("b.txt"), "b.com\n").unwrap();
+    let sources = config.refresh_sources(&client, sources).await.unwrap();
+    assert_eq!(sources[0].content, "b.com\n");
+    assert_eq!(
+        sources[0].parts[0].location,
+        dir.join("b.txt").to_str().unwrap()
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_update_direction() {
+    let path = std::env::temp_dir().join(format!("easy_adblock_update_{}.txt", std::process::id()));
+    std::fs::write(&path, "domain:ads.com\n").unwrap();
+    let rule_src = RuleSrc::new(
+        RuleSrcType::MosdnsFile {
+            path: path.to_str().unwrap().to_string(),
+        },
+        Direction::Block,
+        false,
+    );
+    let client = Config::new(vec![]).http_client().unwrap();
+
+    assert!(rule_src.get(&client, true).await.unwrap().is_empty());
+    let rules = rule_src.get(&client, false).await.unwrap();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].to_string(), "domain:ads.com");
 
-    config.save("config.json").unwrap();
-    let _config = Config::load("config.json").unwrap();
+    std::fs::remove_file(path).unwrap();
 }
@@ -0,0 +1,148 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use serde::Serialize;
+
+use super::{
+    rule::{Rule, RuleType},
+    rule_set::RuleSet,
+};
+
+#[derive(Serialize)]
+pub struct RemovedRule {
+    pub rule: String,
+    pub still_covered: bool, // the new rules still match it, e.g. via a parent `domain` rule
+}
+
+#[derive(Serialize)]
+pub struct ChangedRule {
+    pub old: String,
+    pub new: String,
+}
+
+// Difference between two rule lists after merging each of them
+#[derive(Serialize, Default)]
+pub struct RuleDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<RemovedRule>,
+    pub changed: Vec<ChangedRule>, // same name, different coverage (`full` <-> `domain`)
+}
+
+impl RuleDiff {
+    pub fn new(old_rules: Vec<Rule>, new_rules: Vec<Rule>) -> Self {
+        // keyword and regexp rules don't go into the trie, they are compared as they are
+        let (old_patterns, old_rules) = split_patterns(old_rules);
+        let (new_patterns, new_rules) = split_patterns(new_rules);
+        let old_set = old_rules.into_iter().collect::<RuleSet>();
+        let new_set = new_rules.into_iter().collect::<RuleSet>();
+        let old_types = old_set
+            .iter()
+            .map(|rule| (rule.rule_content, rule.rule_type))
+            .collect::<HashMap<String, RuleType>>();
+        let new_types = new_set
+            .iter()
+            .map(|rule| (rule.rule_content, rule.rule_type))
+            .collect::<HashMap<String, RuleType>>();
+
+        let mut diff = RuleDiff::default();
+        for rule in new_set.iter() {
+            match old_types.get(&rule.rule_content) {
+                None => diff.added.push(rule.to_string()),
+                Some(old_type) if *old_type != rule.rule_type => diff.changed.push(ChangedRule {
+                    old: Rule::new(*old_type, rule.rule_content.clone()).to_string(),
+                    new: rule.to_string(),
+                }),
+                Some(_) => (),
+            }
+        }
+        for rule in old_set.iter() {
+            if !new_types.contains_key(&rule.rule_content) {
+                diff.removed.push(RemovedRule {
+                    still_covered: new_set.covers(&rule),
+                    rule: rule.to_string(),
+                });
+            }
+        }
+        diff.added
+            .extend(new_patterns.difference(&old_patterns).cloned());
+        diff.removed.extend(
+            old_patterns
+                .difference(&new_patterns)
+                .map(|rule| RemovedRule {
+                    rule: rule.clone(),
+                    still_covered: false,
+                }),
+        );
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// (keyword and regexp rules, domain and full rules)
+fn split_patterns(rules: Vec<Rule>) -> (BTreeSet<String>, Vec<Rule>) {
+    let (patterns, rules): (Vec<Rule>, Vec<Rule>) = rules
+        .into_iter()
+        .partition(|rule| matches!(rule.rule_type, RuleType::Keyword | RuleType::Regex));
+    (patterns.iter().map(Rule::to_string).collect(), rules)
+}
+
+impl Display for RuleDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for rule in &self.added {
+            writeln!(f, "+ {}", rule)?;
+        }
+        for rule in &self.removed {
+            if rule.still_covered {
+                writeln!(f, "- {} (still covered)", rule.rule)?;
+            } else {
+                writeln!(f, "- {}", rule.rule)?;
+            }
+        }
+        for rule in &self.changed {
+            writeln!(f, "~ {} -> {}", rule.old, rule.new)?;
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+#[test]
+fn test_diff() {
+    let old_rules = vec![
+        Rule::new(RuleType::Full, "a.ads.com".to_string()),
+        Rule::new(RuleType::Full, "track.net".to_string()),
+        Rule::new(RuleType::Domain, "same.org".to_string()),
+        Rule::new(RuleType::Full, "gone.io".to_string()),
+        Rule::new(RuleType::Keyword, "tracker".to_string()),
+        Rule::new(RuleType::Regex, "^ad[0-9]+\\.".to_string()),
+    ];
+    let new_rules = vec![
+        Rule::new(RuleType::Domain, "ads.com".to_string()),
+        Rule::new(RuleType::Domain, "track.net".to_string()),
+        Rule::new(RuleType::Domain, "same.org".to_string()),
+        Rule::new(RuleType::Keyword, "tracker".to_string()),
+        Rule::new(RuleType::Keyword, "banner".to_string()),
+    ];
+    let diff = RuleDiff::new(old_rules, new_rules);
+    assert_eq!(diff.added, vec!["domain:ads.com", "keyword:banner"]);
+    assert_eq!(diff.removed.len(), 3);
+    assert_eq!(diff.removed[0].rule, "full:a.ads.com");
+    assert!(diff.removed[0].still_covered);
+    assert_eq!(diff.removed[1].rule, "full:gone.io");
+    assert!(!diff.removed[1].still_covered);
+    assert_eq!(diff.removed[2].rule, "regexp:^ad[0-9]+\\.");
+    assert!(!diff.removed[2].still_covered);
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].old, "full:track.net");
+    assert_eq!(diff.changed[0].new, "domain:track.net");
+}
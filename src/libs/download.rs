@@ -1,6 +1,242 @@
-use super::rule::Rule;
-use std::future::Future;
+use super::{
+    archive,
+    error::{Error, Result},
+    format::RuleFormat,
+    request::HttpClient,
+    rule::Rule,
+};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::{future::Future, path::PathBuf, time::SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct SourceContent {
+    pub location: String,
+    pub fetched_at: SystemTime,
+    pub sha256: String,
+    pub content: String,
+    pub served_by: Option<String>, // mirror the content came from, if not `location`
+    pub parts: Vec<SourcePart>,    // files `content` was joined from, see `read_files`
+}
+
+// One of the files of a source read from several, its lines follow those
+// of the parts before it in `SourceContent::content`
+#[derive(Debug, Clone)]
+pub struct SourcePart {
+    pub location: String,
+    pub format: RuleFormat,
+    pub lines: usize,
+}
+
+// A line of a source with the file it was read from and its format
+pub struct SourceLine<'a> {
+    pub location: &'a str,
+    pub line_number: usize, // in the file
+    pub format: Option<RuleFormat>,
+    pub line: &'a str,
+}
+
+impl SourceLine<'_> {
+    pub fn parse(&self, want_accept_rule: bool) -> Option<Rule> {
+        self.format?.parse_line(self.line, want_accept_rule)
+    }
+}
+
+impl SourceContent {
+    pub fn new(location: String, content: String) -> Self {
+        SourceContent {
+            location,
+            fetched_at: SystemTime::now(),
+            sha256: format!("{:x}", Sha256::digest(content.as_bytes())),
+            content,
+            served_by: None,
+            parts: vec![],
+        }
+    }
+
+    // Every line with its origin, `format` applies to sources without parts
+    pub fn lines(&self, format: Option<RuleFormat>) -> impl Iterator<Item = SourceLine<'_>> {
+        let mut parts = self.parts.iter();
+        let mut part = parts.next();
+        let mut first_index = 0; // of the first line of `part` in `content`
+        self.content.lines().enumerate().map(move |(index, line)| {
+            while let Some(current) = part.filter(|part| index >= first_index + part.lines) {
+                first_index += current.lines;
+                part = parts.next();
+            }
+            match part {
+                Some(part) => SourceLine {
+                    location: &part.location,
+                    line_number: index - first_index + 1,
+                    format: Some(part.format),
+                    line,
+                },
+                None => SourceLine {
+                    location: &self.location,
+                    line_number: index + 1,
+                    format,
+                    line,
+                },
+            }
+        })
+    }
+}
+
+// A source as it was read or downloaded, possibly compressed or archived
+#[derive(Debug, Clone)]
+pub struct RawSource {
+    pub location: String,
+    pub served_by: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
+impl RawSource {
+    // The text of the source, `member` selects the file of an archive
+    pub fn unpack(self, member: Option<&str>) -> Result<SourceContent> {
+        let content = archive::unpack(&self.location, self.bytes, member)?;
+        let mut source = SourceContent::new(self.location, content);
+        source.served_by = self.served_by;
+        Ok(source)
+    }
+}
+
+// Every file in the directory `pattern`, or matching the glob `pattern`
+// such as `/etc/adblock/custom.d/*.txt`, sorted by path. Hidden files are
+// left out unless the pattern spells out the leading dot.
+pub fn list_files(pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern = if std::path::Path::new(pattern).is_dir() {
+        glob::Pattern::escape(pattern)
+            .trim_end_matches('/')
+            .to_string()
+            + "/*"
+    } else {
+        pattern.to_string()
+    };
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+    let paths = glob::glob_with(&pattern, options)
+        .map_err(|e| Error::Config(format!("{}: invalid pattern: {}", pattern, e)))?;
+    let mut files = vec![];
+    for path in paths {
+        let path = path.map_err(|e| {
+            let path = e.path().to_string_lossy().into_owned();
+            Error::io(&path, e.into())
+        })?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// The files of `list_files` joined into one source, each read in `format`
+// or the one detected from its content. Compressed files are unpacked.
+pub fn read_files(pattern: &str, format: Option<RuleFormat>) -> Result<SourceContent> {
+    let mut content = String::new();
+    let mut parts = vec![];
+    for path in list_files(pattern)? {
+        let location = path.to_string_lossy().into_owned();
+        let bytes = std::fs::read(&path).map_err(|e| Error::io(&location, e))?;
+        let mut text = archive::unpack(&location, bytes, None)?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        parts.push(SourcePart {
+            format: format.unwrap_or_else(|| RuleFormat::detect(&text)),
+            lines: text.lines().count(),
+            location,
+        });
+        content += &text;
+    }
+    if parts.is_empty() {
+        warn!("{} matches no files", pattern);
+    }
+    let mut source = SourceContent::new(pattern.to_string(), content);
+    source.parts = parts;
+    Ok(source)
+}
+
+// Last path segment of a URL or file path, without the query
+pub fn file_name(location: &str) -> &str {
+    let path = location.split(['?', '#']).next().unwrap_or(location);
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
 
 pub trait Update {
-    fn get(&self, accept_rule: bool) -> impl Future<Output = Result<Vec<Rule>, String>>;
+    fn fetch(&self, client: &HttpClient) -> impl Future<Output = Result<SourceContent>>;
+    fn parse(&self, content: &str, accept_rule: bool) -> Vec<Rule>;
+
+    fn get(
+        &self,
+        client: &HttpClient,
+        accept_rule: bool,
+    ) -> impl Future<Output = Result<Vec<Rule>>> {
+        async move {
+            let source = self.fetch(client).await?;
+            Ok(self.parse(&source.content, accept_rule))
+        }
+    }
+}
+
+#[test]
+fn test_read_files() {
+    let dir = std::env::temp_dir().join(format!("easy_adblock_files_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("b.txt"), "# team b\n||ads.com^\n@@||ok.com^").unwrap();
+    std::fs::write(dir.join("a.txt"), "tracker.com\n").unwrap();
+    std::fs::write(dir.join("c.list"), "domain:ads.net\n").unwrap();
+    std::fs::write(dir.join(".a.txt.swp"), "junk\n").unwrap();
+    std::fs::write(dir.join("nested/d.txt"), "nested.com\n").unwrap();
+    let dir_path = dir.to_str().unwrap();
+
+    let source = read_files(dir_path, None).unwrap();
+    assert_eq!(source.location, dir_path);
+    let formats: Vec<_> = source.parts.iter().map(|part| part.format).collect();
+    assert_eq!(
+        formats,
+        [RuleFormat::Pure, RuleFormat::Adguard, RuleFormat::Mosdns]
+    );
+    let lines: Vec<_> = source
+        .lines(None)
+        .map(|line| (file_name(line.location), line.line_number, line.format))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            ("a.txt", 1, Some(RuleFormat::Pure)),
+            ("b.txt", 1, Some(RuleFormat::Adguard)),
+            ("b.txt", 2, Some(RuleFormat::Adguard)),
+            ("b.txt", 3, Some(RuleFormat::Adguard)),
+            ("c.list", 1, Some(RuleFormat::Mosdns)),
+        ]
+    );
+    let rules: Vec<_> = source
+        .lines(None)
+        .filter_map(|line| line.parse(false))
+        .map(|rule| rule.rule_content)
+        .collect();
+    assert_eq!(rules, ["tracker.com", "ads.com", "ads.net"]);
+
+    // a pattern picks the files, a chosen format applies to all of them
+    let pattern = format!("{}/*.txt", dir_path);
+    let source = read_files(&pattern, Some(RuleFormat::Pure)).unwrap();
+    assert_eq!(source.parts.len(), 2);
+    assert!(source
+        .parts
+        .iter()
+        .all(|part| part.format == RuleFormat::Pure));
+
+    let source = read_files(&format!("{}/*.missing", dir_path), None).unwrap();
+    assert!(source.content.is_empty() && source.parts.is_empty());
+    assert!(read_files(&format!("{}/[", dir_path), None).is_err());
+
+    // without parts every line has the source's own location and format
+    let source = SourceContent::new("rules.txt".to_string(), "a.com\nb.com\n".to_string());
+    let line = source.lines(Some(RuleFormat::Pure)).nth(1).unwrap();
+    assert_eq!((line.location, line.line_number), ("rules.txt", 2));
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
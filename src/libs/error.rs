@@ -0,0 +1,127 @@
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Errors of the crate. Display only describes the failure itself, the
+// underlying error is available through `source()`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // DNS, connection, TLS or body errors, the request got no usable response
+    #[error("{url}: request failed")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{url}: request timed out")]
+    Timeout {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{url}: HTTP status {status}")]
+    HttpStatus { url: String, status: u16 },
+    #[error("{path}: I/O error")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}: {message}", position(.location, .line, .column))]
+    Parse {
+        location: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    // the content doesn't match its pinned hash, checksum file or signature
+    #[error("{location}: integrity check failed: {message}")]
+    Integrity { location: String, message: String },
+    #[error("{0}")]
+    Config(String),
+}
+
+impl Error {
+    pub fn network(url: &str, source: reqwest::Error) -> Self {
+        let url = url.to_string();
+        if source.is_timeout() {
+            Error::Timeout { url, source }
+        } else {
+            Error::Network { url, source }
+        }
+    }
+
+    pub fn io(path: &str, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn parse(location: &str, message: String) -> Self {
+        Error::Parse {
+            location: location.to_string(),
+            line: None,
+            column: None,
+            message,
+        }
+    }
+
+    // Whether trying again later may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Network { source, .. } => !source.is_builder(),
+            Error::Timeout { .. } => true,
+            Error::HttpStatus { status, .. } => *status == 429 || *status >= 500,
+            Error::Io { .. } | Error::Parse { .. } | Error::Integrity { .. } | Error::Config(_) => {
+                false
+            }
+        }
+    }
+
+    // The error and every error it was caused by, separated by `: `. Some
+    // errors already print their cause, those aren't repeated.
+    pub fn chain(&self) -> String {
+        let mut chain = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            let message = error.to_string();
+            if !chain.contains(&message) {
+                chain.push_str(&format!(": {}", message));
+            }
+            source = error.source();
+        }
+        chain
+    }
+}
+
+fn position(location: &str, line: &Option<usize>, column: &Option<usize>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!("{}:{}:{}", location, line, column),
+        (Some(line), None) => format!("{}:{}", location, line),
+        _ => location.to_string(),
+    }
+}
+
+#[test]
+fn test_error() {
+    let error = Error::Parse {
+        location: "config.json".to_string(),
+        line: Some(3),
+        column: Some(7),
+        message: "unknown field `rejct`".to_string(),
+    };
+    assert_eq!(error.to_string(), "config.json:3:7: unknown field `rejct`");
+
+    let error = Error::io(
+        "rules.txt",
+        std::io::Error::new(std::io::ErrorKind::NotFound, "not found"),
+    );
+    assert_eq!(error.chain(), "rules.txt: I/O error: not found");
+    assert!(matches!(
+        std::error::Error::source(&error)
+            .unwrap()
+            .downcast_ref::<std::io::Error>()
+            .unwrap()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    ));
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::{Error, Result},
+    geosite::load_geosite_category,
+    rule::{Rule, RuleType},
+};
+
+// Text formats rule lists are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleFormat {
+    Mosdns,  // domain:example.com / full:example.com
+    Pure,    // example.com, one domain per line
+    Adguard, // ||example.com^ / @@||example.com^
+}
+
+impl RuleFormat {
+    pub fn parse_line(&self, line: &str, want_accept_rule: bool) -> Option<Rule> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        match self {
+            RuleFormat::Mosdns => {
+                let rule_string = line.split_once(':')?;
+                let rule_type = match rule_string.0.trim() {
+                    "domain" => RuleType::Domain,
+                    "full" => RuleType::Full,
+                    _ => return None,
+                };
+                // ignore a trailing comment such as the provenance of the rule
+                let rule_content = rule_string.1.split('#').next()?.trim().to_string();
+                Some(Rule::new(rule_type, rule_content))
+            }
+            RuleFormat::Pure => Some(Rule::new(RuleType::Domain, line.to_string())),
+            RuleFormat::Adguard => {
+                let prefix = if want_accept_rule { "@@||" } else { "||" };
+                if line.starts_with(prefix) && line.ends_with('^') {
+                    let rule_content = line.trim_start_matches(prefix).trim_end_matches('^');
+                    Some(Rule::new(RuleType::Domain, rule_content.to_string()))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn parse(&self, content: &str, want_accept_rule: bool) -> Vec<Rule> {
+        content
+            .lines()
+            .filter_map(|line| self.parse_line(line, want_accept_rule))
+            .collect()
+    }
+
+    // Guess the format from the first lines which aren't comments
+    pub fn detect(content: &str) -> Self {
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+            .take(100)
+            .peekable();
+        if lines.peek().is_none() {
+            return RuleFormat::Mosdns;
+        }
+        let mut pure = true;
+        for line in lines {
+            if line.starts_with("||") || line.starts_with("@@||") {
+                return RuleFormat::Adguard;
+            }
+            if ["domain:", "full:", "keyword:", "regexp:"]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+            {
+                return RuleFormat::Mosdns;
+            }
+            pure &= !line.contains(char::is_whitespace);
+        }
+        if pure {
+            RuleFormat::Pure
+        } else {
+            RuleFormat::Mosdns
+        }
+    }
+}
+
+// Read a rule file, detecting its format. A geosite.dat, by its `.dat`
+// extension or binary content, is read for the rules of `geosite_category`.
+pub fn load_rule_file(
+    path: &str,
+    want_accept_rule: bool,
+    geosite_category: &str,
+) -> Result<Vec<Rule>> {
+    let bytes = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+    let is_dat = std::path::Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("dat"));
+    let content = match String::from_utf8(bytes) {
+        Ok(content) if !is_dat => content,
+        Ok(content) => return load_geosite_category(path, content.as_bytes(), geosite_category),
+        Err(e) if e.as_bytes().starts_with(&[0x0a]) => {
+            return load_geosite_category(path, e.as_bytes(), geosite_category)
+        }
+        Err(e) => return Err(Error::parse(path, format!("not a text rule file: {}", e))),
+    };
+    Ok(RuleFormat::detect(&content).parse(&content, want_accept_rule))
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(
+        RuleFormat::detect("# header\ndomain:example.com\nfull:a.example.com\n"),
+        RuleFormat::Mosdns
+    );
+    assert_eq!(
+        RuleFormat::detect("! adguard\n||ads.com^\n@@||good.com^\n"),
+        RuleFormat::Adguard
+    );
+    assert_eq!(RuleFormat::detect("ads.com\ntrack.net\n"), RuleFormat::Pure);
+    assert_eq!(
+        RuleFormat::Mosdns
+            .parse_line("domain:ads.com # a.txt, b.txt", false)
+            .map(|rule| rule.to_string()),
+        Some("domain:ads.com".to_string())
+    );
+}
@@ -0,0 +1,309 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::{Error, Result},
+    rule::{Rule, RuleType},
+};
+
+// v2ray `router.Domain.Type`
+const DOMAIN_TYPE_PLAIN: u64 = 0;
+const DOMAIN_TYPE_REGEX: u64 = 1;
+const DOMAIN_TYPE_ROOT_DOMAIN: u64 = 2;
+const DOMAIN_TYPE_FULL: u64 = 3;
+
+const WIRE_TYPE_VARINT: u64 = 0;
+const WIRE_TYPE_I64: u64 = 1;
+const WIRE_TYPE_LEN: u64 = 2;
+const WIRE_TYPE_I32: u64 = 5;
+
+#[derive(Deserialize, Serialize)]
+pub struct GeositeOutput {
+    pub path: String,
+    #[serde(default = "default_accept_category")]
+    pub accept_category: String,
+    #[serde(default = "default_reject_category")]
+    pub reject_category: String,
+}
+
+pub fn default_accept_category() -> String {
+    "category-ads-allow".to_string()
+}
+
+pub fn default_reject_category() -> String {
+    "category-ads-merged".to_string()
+}
+
+pub struct GeoSite<'a> {
+    pub country_code: &'a str,
+    pub rules: &'a [Rule],
+}
+
+impl GeositeOutput {
+    pub fn new(path: String) -> Self {
+        GeositeOutput {
+            path,
+            accept_category: default_accept_category(),
+            reject_category: default_reject_category(),
+        }
+    }
+
+    pub fn write(&self, accept_rules: &[Rule], reject_rules: &[Rule]) -> Result<()> {
+        let geosite_list = encode_geosite_list(&[
+            GeoSite {
+                country_code: &self.accept_category,
+                rules: accept_rules,
+            },
+            GeoSite {
+                country_code: &self.reject_category,
+                rules: reject_rules,
+            },
+        ]);
+        std::fs::File::create(&self.path)
+            .and_then(|mut file| file.write_all(&geosite_list))
+            .map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+// Encode as v2ray `router.GeoSiteList`
+pub fn encode_geosite_list(geosites: &[GeoSite]) -> Vec<u8> {
+    let mut buf = vec![];
+    for geosite in geosites {
+        // GeoSiteList.entry = 1
+        write_len_delimited(&mut buf, 1, &encode_geosite(geosite));
+    }
+    buf
+}
+
+fn encode_geosite(geosite: &GeoSite) -> Vec<u8> {
+    let mut buf = vec![];
+    // GeoSite.country_code = 1, v2ray matches categories in upper case
+    write_len_delimited(&mut buf, 1, geosite.country_code.to_uppercase().as_bytes());
+    for rule in geosite.rules {
+        // GeoSite.domain = 2
+        write_len_delimited(&mut buf, 2, &encode_domain(rule));
+    }
+    buf
+}
+
+fn encode_domain(rule: &Rule) -> Vec<u8> {
+    let mut buf = vec![];
+    let domain_type = match rule.rule_type {
+        RuleType::Keyword => DOMAIN_TYPE_PLAIN,
+        RuleType::Regex => DOMAIN_TYPE_REGEX,
+        RuleType::Domain => DOMAIN_TYPE_ROOT_DOMAIN,
+        RuleType::Full => DOMAIN_TYPE_FULL,
+    };
+    // Domain.type = 1, proto3 omits default values
+    if domain_type != DOMAIN_TYPE_PLAIN {
+        write_varint(&mut buf, (1 << 3) | WIRE_TYPE_VARINT);
+        write_varint(&mut buf, domain_type);
+    }
+    // Domain.value = 2
+    write_len_delimited(&mut buf, 2, rule.rule_content.as_bytes());
+    buf
+}
+
+fn write_len_delimited(buf: &mut Vec<u8>, field_number: u64, data: &[u8]) {
+    write_varint(buf, (field_number << 3) | WIRE_TYPE_LEN);
+    write_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+// The rules of `category` in a geosite.dat, such as one written by
+// `GeositeOutput::write`
+pub fn load_geosite_category(path: &str, bytes: &[u8], category: &str) -> Result<Vec<Rule>> {
+    let geosites = decode_geosite_list(bytes).map_err(|e| Error::parse(path, e))?;
+    let categories: Vec<&str> = geosites.iter().map(|(code, _)| code.as_str()).collect();
+    geosites
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(category))
+        .map(|(_, rules)| rules.clone())
+        .ok_or_else(|| {
+            Error::parse(
+                path,
+                format!(
+                    "no category {}, it has {}",
+                    category.to_uppercase(),
+                    categories.join(", ")
+                ),
+            )
+        })
+}
+
+// Decode a v2ray `router.GeoSiteList` as (country code, rules) pairs,
+// fields other than the ones written by `encode_geosite_list` are skipped
+pub fn decode_geosite_list(bytes: &[u8]) -> std::result::Result<Vec<(String, Vec<Rule>)>, String> {
+    let mut geosites = vec![];
+    for field in fields(bytes) {
+        if let (1, Field::Len(geosite)) = field? {
+            geosites.push(decode_geosite(geosite)?);
+        }
+    }
+    Ok(geosites)
+}
+
+fn decode_geosite(bytes: &[u8]) -> std::result::Result<(String, Vec<Rule>), String> {
+    let mut country_code = String::new();
+    let mut rules = vec![];
+    for field in fields(bytes) {
+        match field? {
+            (1, Field::Len(code)) => country_code = String::from_utf8_lossy(code).into_owned(),
+            (2, Field::Len(domain)) => rules.push(decode_domain(domain)?),
+            _ => (),
+        }
+    }
+    Ok((country_code, rules))
+}
+
+fn decode_domain(bytes: &[u8]) -> std::result::Result<Rule, String> {
+    let mut domain_type = DOMAIN_TYPE_PLAIN;
+    let mut value = String::new();
+    for field in fields(bytes) {
+        match field? {
+            (1, Field::Varint(number)) => domain_type = number,
+            (2, Field::Len(content)) => value = String::from_utf8_lossy(content).into_owned(),
+            _ => (),
+        }
+    }
+    let rule_type = match domain_type {
+        DOMAIN_TYPE_PLAIN => RuleType::Keyword,
+        DOMAIN_TYPE_REGEX => RuleType::Regex,
+        DOMAIN_TYPE_ROOT_DOMAIN => RuleType::Domain,
+        DOMAIN_TYPE_FULL => RuleType::Full,
+        _ => return Err(format!("unknown domain type {} of {}", domain_type, value)),
+    };
+    Ok(Rule::new(rule_type, value))
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Len(&'a [u8]),
+    Fixed, // 32 and 64 bit values, never read
+}
+
+// The (field number, value) pairs of a protobuf message
+fn fields(mut bytes: &[u8]) -> impl Iterator<Item = std::result::Result<(u64, Field<'_>), String>> {
+    std::iter::from_fn(move || {
+        if bytes.is_empty() {
+            return None;
+        }
+        let mut next = || -> std::result::Result<(u64, Field), String> {
+            let key = read_varint(&mut bytes)?;
+            let field = match key & 0x7 {
+                WIRE_TYPE_VARINT => Field::Varint(read_varint(&mut bytes)?),
+                WIRE_TYPE_LEN => {
+                    let len = read_varint(&mut bytes)? as usize;
+                    if len > bytes.len() {
+                        return Err("truncated protobuf message".to_string());
+                    }
+                    let (value, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Field::Len(value)
+                }
+                wire_type @ (WIRE_TYPE_I64 | WIRE_TYPE_I32) => {
+                    let len = if wire_type == WIRE_TYPE_I64 { 8 } else { 4 };
+                    if len > bytes.len() {
+                        return Err("truncated protobuf message".to_string());
+                    }
+                    bytes = &bytes[len..];
+                    Field::Fixed
+                }
+                wire_type => return Err(format!("unsupported protobuf wire type {}", wire_type)),
+            };
+            Ok((key >> 3, field))
+        };
+        let field = next();
+        if field.is_err() {
+            bytes = &[]; // nothing sensible follows a malformed field
+        }
+        Some(field)
+    })
+}
+
+fn read_varint(bytes: &mut &[u8]) -> std::result::Result<u64, String> {
+    let mut value = 0;
+    for (index, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[index + 1..];
+            return Ok(value);
+        }
+    }
+    Err("malformed protobuf varint".to_string())
+}
+
+#[test]
+fn test_encode_geosite_list() {
+    let rules = vec![
+        Rule::new(RuleType::Domain, "ads.com".to_string()),
+        Rule::new(RuleType::Full, "a.b".to_string()),
+        Rule::new(RuleType::Keyword, "ad".to_string()),
+    ];
+    let encoded = encode_geosite_list(&[GeoSite {
+        country_code: "ads",
+        rules: &rules,
+    }]);
+    let expected: Vec<u8> = [
+        &[0x0a, 33][..],
+        &[0x0a, 3],
+        b"ADS",
+        &[0x12, 11, 0x08, 2, 0x12, 7],
+        b"ads.com",
+        &[0x12, 7, 0x08, 3, 0x12, 3],
+        b"a.b",
+        &[0x12, 4, 0x12, 2],
+        b"ad",
+    ]
+    .concat();
+    assert_eq!(encoded, expected);
+}
+
+#[test]
+fn test_decode_geosite_list() {
+    let accept = vec![Rule::new(RuleType::Full, "ok.com".to_string())];
+    let reject = vec![
+        Rule::new(RuleType::Domain, "ads.com".to_string()),
+        Rule::new(RuleType::Regex, "^ad[0-9]+\\.".to_string()),
+        Rule::new(RuleType::Keyword, "tracker".to_string()),
+    ];
+    let mut encoded = encode_geosite_list(&[
+        GeoSite {
+            country_code: "category-ads-allow",
+            rules: &accept,
+        },
+        GeoSite {
+            country_code: "category-ads-merged",
+            rules: &reject,
+        },
+    ]);
+    // an entry with a resource hash (`GeoSite.resource_hash = 3`) and an
+    // attribute (`Domain.attribute = 3`), as in files from other tools
+    encoded.extend([0x0a, 18, 0x0a, 2, b'C', b'N', 0x1a, 2, 0xab, 0xcd]);
+    encoded.extend([0x12, 8, 0x12, 2, b'c', b'n', 0x1a, 2, 0x0a, 0]);
+
+    let strings = |rules: &[Rule]| rules.iter().map(Rule::to_string).collect::<Vec<_>>();
+    let decoded = decode_geosite_list(&encoded).unwrap();
+    let codes: Vec<&str> = decoded.iter().map(|(code, _)| code.as_str()).collect();
+    assert_eq!(codes, ["CATEGORY-ADS-ALLOW", "CATEGORY-ADS-MERGED", "CN"]);
+    assert_eq!(strings(&decoded[1].1), strings(&reject));
+    assert_eq!(strings(&decoded[2].1), ["keyword:cn"]);
+
+    let loaded = load_geosite_category("geosite.dat", &encoded, "category-ads-allow").unwrap();
+    assert_eq!(strings(&loaded), strings(&accept));
+    let error = load_geosite_category("geosite.dat", &encoded, "missing").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "geosite.dat: no category MISSING, it has CATEGORY-ADS-ALLOW, CATEGORY-ADS-MERGED, CN"
+    );
+    assert!(decode_geosite_list(&encoded[..encoded.len() - 3]).is_err());
+}
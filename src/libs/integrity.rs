@@ -0,0 +1,218 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    download::{file_name, RawSource},
+    error::{Error, Result},
+    request::{HttpClient, RequestMethod, RequestStructure},
+};
+
+// Checks a fetched source must pass before it's used, the `integrity`
+// section of a source. Every check configured has to pass.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Integrity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>, // pinned hash of the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_url: Option<String>, // `sha256sum` output listing the source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_url: Option<String>, // detached minisign or base64 ed25519 signature
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub public_keys: Vec<String>, // any of them may have signed the source
+    #[serde(default)]
+    pub on_failure: OnFailure,
+}
+
+// What happens to a source failing its checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    #[default]
+    Fail, // the run fails
+    Cache, // the last cached copy is used, it passed the checks when it was stored
+    Skip,  // the source is left out
+}
+
+impl Integrity {
+    // Checks the source as downloaded, before it's decompressed
+    pub async fn verify(&self, client: &HttpClient, source: &RawSource) -> Result<()> {
+        let fail = |message: String| Error::Integrity {
+            location: source.location.clone(),
+            message,
+        };
+        self.verify_offline(source)?;
+        let sha256 = format!("{:x}", Sha256::digest(&source.bytes));
+        if let Some(url) = &self.checksum_url {
+            let checksums = download(&client.for_url(url, &source.location), url).await?;
+            let name = file_name(&source.location);
+            let expected = find_checksum(&checksums, name)
+                .ok_or_else(|| fail(format!("{} lists no SHA-256 for {}", url, name)))?;
+            if !expected.eq_ignore_ascii_case(&sha256) {
+                return Err(fail(format!(
+                    "SHA-256 is {}, {} lists {}",
+                    sha256, url, expected
+                )));
+            }
+        }
+        if let Some(url) = &self.signature_url {
+            let signature = download(&client.for_url(url, &source.location), url).await?;
+            let mut reasons = vec![];
+            for public_key in &self.public_keys {
+                match verify_signature(public_key, &source.bytes, &signature) {
+                    Ok(()) => return Ok(()),
+                    Err(reason) => reasons.push(reason),
+                }
+            }
+            return Err(fail(format!(
+                "signature {} not verified: {}",
+                url,
+                reasons.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    // The checks which don't download anything, only the pinned hash
+    pub fn verify_offline(&self, source: &RawSource) -> Result<()> {
+        let Some(pinned) = &self.sha256 else {
+            return Ok(());
+        };
+        let sha256 = format!("{:x}", Sha256::digest(&source.bytes));
+        if pinned.eq_ignore_ascii_case(&sha256) {
+            Ok(())
+        } else {
+            Err(Error::Integrity {
+                location: source.location.clone(),
+                message: format!("SHA-256 is {}, {} is pinned", sha256, pinned),
+            })
+        }
+    }
+}
+
+async fn download(client: &HttpClient, url: &str) -> Result<String> {
+    let request = RequestStructure::new_default(RequestMethod::GET, url.to_string(), "".to_owned());
+    let body = request.execute_with_retry(client).await?.2;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+// The hash listed for `name` in `sha256sum` output. A file holding only a
+// hash, without any name, applies to the source whatever it's called.
+fn find_checksum<'a>(checksums: &'a str, name: &str) -> Option<&'a str> {
+    let mut unnamed = vec![];
+    for line in checksums.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(hash) = fields.next() else {
+            continue;
+        };
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        // `*` marks files hashed in binary mode
+        match fields.next().map(|listed| listed.trim_start_matches('*')) {
+            Some(listed) if file_name(listed) == name => return Some(hash),
+            Some(_) => (),
+            None => unnamed.push(hash),
+        }
+    }
+    match unnamed[..] {
+        [hash] => Some(hash),
+        _ => None,
+    }
+}
+
+enum PublicKey {
+    Minisign(minisign_verify::PublicKey),
+    Ed25519(VerifyingKey),
+}
+
+// A minisign key (`RW...`) or a base64 ed25519 key
+fn parse_public_key(public_key: &str) -> std::result::Result<PublicKey, String> {
+    let public_key = public_key.trim();
+    let key = BASE64_STANDARD
+        .decode(public_key)
+        .map_err(|e| format!("malformed public key {}: {}", public_key, e))?;
+    match key.len() {
+        42 => minisign_verify::PublicKey::from_base64(public_key)
+            .map(PublicKey::Minisign)
+            .map_err(|e| format!("malformed minisign key {}: {}", public_key, e)),
+        32 => VerifyingKey::from_bytes(key[..].try_into().unwrap())
+            .map(PublicKey::Ed25519)
+            .map_err(|e| format!("malformed ed25519 key {}: {}", public_key, e)),
+        _ => Err(format!(
+            "{} is neither a minisign nor an ed25519 public key",
+            public_key
+        )),
+    }
+}
+
+// Problem of a configured public key, if any
+pub fn check_public_key(public_key: &str) -> std::result::Result<(), String> {
+    parse_public_key(public_key).map(|_| ())
+}
+
+// Whether `public_key` signed `content`
+fn verify_signature(
+    public_key: &str,
+    content: &[u8],
+    signature: &str,
+) -> std::result::Result<(), String> {
+    match parse_public_key(public_key)? {
+        PublicKey::Minisign(key) => {
+            let signature = minisign_verify::Signature::decode(signature)
+                .map_err(|e| format!("malformed minisign signature: {}", e))?;
+            key.verify(content, &signature, true)
+                .map_err(|e| format!("{} with key {}", e, public_key))
+        }
+        PublicKey::Ed25519(key) => {
+            let signature = BASE64_STANDARD
+                .decode(signature.trim())
+                .ok()
+                .and_then(|signature| Signature::from_slice(&signature).ok())
+                .ok_or_else(|| "malformed ed25519 signature".to_string())?;
+            key.verify_strict(content, &signature)
+                .map_err(|_| format!("signature mismatch with key {}", public_key))
+        }
+    }
+}
+
+#[test]
+fn test_integrity() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let checksums = "\
+        9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  lists/ads.txt\n\
+        0000000000000000000000000000000000000000000000000000000000000000 *other.txt\n";
+    assert_eq!(
+        find_checksum(checksums, file_name("https://example.com/ads.txt?v=2")),
+        Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+    );
+    assert_eq!(find_checksum(checksums, "missing.txt"), None);
+    assert!(find_checksum(
+        "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08\n",
+        "x"
+    )
+    .is_some());
+
+    // from the minisign test vectors
+    let minisign_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    let minisig = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+    assert!(verify_signature(minisign_key, b"test", minisig).is_ok());
+    assert!(verify_signature(minisign_key, b"tampered", minisig).is_err());
+
+    let signing_key = SigningKey::from_bytes(&[7; 32]);
+    let ed25519_key = BASE64_STANDARD.encode(signing_key.verifying_key().as_bytes());
+    let signature = BASE64_STANDARD.encode(signing_key.sign(b"test").to_bytes());
+    assert!(verify_signature(&ed25519_key, b"test", &signature).is_ok());
+    assert!(verify_signature(&ed25519_key, b"tampered", &signature).is_err());
+    assert!(verify_signature(&ed25519_key, b"test", minisig).is_err());
+
+    assert!(check_public_key(minisign_key).is_ok() && check_public_key(&ed25519_key).is_ok());
+    assert!(check_public_key("not a key").is_err());
+    assert!(check_public_key(&BASE64_STANDARD.encode([1; 16])).is_err());
+}
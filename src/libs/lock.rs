@@ -0,0 +1,135 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::RuleSrc,
+    download::SourceContent,
+    error::{Error, Result},
+};
+
+pub const DEFAULT_LOCK_PATH: &str = "./rules.lock";
+
+// The exact content of every enabled source used by a build, so that the
+// build can be repeated from the cache later, see `Config::locked_sources`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Lock {
+    pub sources: Vec<LockedSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LockedSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub location: String, // URL or file path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>, // mirror the content came from
+    pub fetched_at: String, // RFC 3339
+    pub size: usize,      // bytes
+    pub sha256: String,
+}
+
+impl Lock {
+    pub fn new(rule_src: &[RuleSrc], sources: &[SourceContent]) -> Self {
+        Lock {
+            sources: rule_src
+                .iter()
+                .zip(sources)
+                .filter(|(rule_src, _)| rule_src.enabled)
+                .map(|(rule_src, source)| LockedSource {
+                    name: rule_src.name.clone(),
+                    location: source.location.clone(),
+                    served_by: source.served_by.clone(),
+                    fetched_at: humantime::format_rfc3339_seconds(source.fetched_at).to_string(),
+                    size: source.content.len(),
+                    sha256: source.sha256.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        serde_json::from_str(&content).map_err(|e| Error::Parse {
+            location: path.to_string(),
+            line: Some(e.line()),
+            column: Some(e.column()),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, content + "\n").map_err(|e| Error::io(path, e))
+    }
+
+    pub fn find(&self, location: &str) -> Option<&LockedSource> {
+        self.sources
+            .iter()
+            .find(|source| source.location == location)
+    }
+}
+
+impl LockedSource {
+    // The locked source read again, it must have the locked hash
+    pub fn restore(&self, mut source: SourceContent) -> Result<SourceContent> {
+        if source.sha256 != self.sha256 {
+            return Err(Error::Integrity {
+                location: self.location.clone(),
+                message: format!(
+                    "SHA-256 is {}, the lock records {}",
+                    source.sha256, self.sha256
+                ),
+            });
+        }
+        source.served_by = self.served_by.clone();
+        source.fetched_at =
+            humantime::parse_rfc3339(&self.fetched_at).unwrap_or(SystemTime::UNIX_EPOCH);
+        Ok(source)
+    }
+}
+
+#[test]
+fn test_lock() {
+    let rule_src = vec![
+        RuleSrc::from_adguard_home_rule("https://example.com/rules.txt".to_string(), true),
+        RuleSrc::from_mosdns_file("block.txt".to_string(), false, false),
+    ];
+    let mut sources = vec![
+        SourceContent::new(rule_src[0].src_type.location(), "||ads.com^\n".to_string()),
+        SourceContent::new(
+            rule_src[1].src_type.location(),
+            "domain:ads.net\n".to_string(),
+        ),
+    ];
+    sources[0].served_by = Some("https://mirror.example.com/rules.txt".to_string());
+    let mut lock = Lock::new(&rule_src, &sources);
+    let path = std::env::temp_dir().join(format!("easy_adblock_lock_{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    lock.save(path).unwrap();
+    assert_eq!(Lock::load(path).unwrap(), lock);
+    std::fs::remove_file(path).unwrap();
+
+    let locked = lock.find("https://example.com/rules.txt").unwrap();
+    assert_eq!(locked.size, 11);
+    let restore = |content: &str| {
+        locked.restore(SourceContent::new(
+            locked.location.clone(),
+            content.to_string(),
+        ))
+    };
+    let restored = restore("||ads.com^\n").unwrap();
+    assert_eq!(restored.served_by, sources[0].served_by);
+    assert_eq!(
+        restored.fetched_at,
+        humantime::parse_rfc3339(&locked.fetched_at).unwrap()
+    );
+    assert!(restore("||tampered.com^\n").is_err());
+
+    // disabled sources aren't locked
+    let mut rule_src = rule_src;
+    rule_src[1].enabled = false;
+    lock = Lock::new(&rule_src, &sources);
+    assert_eq!(lock.sources.len(), 1);
+    assert!(lock.find("block.txt").is_none());
+}
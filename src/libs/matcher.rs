@@ -0,0 +1,200 @@
+use aho_corasick::AhoCorasick;
+use regex::RegexSet;
+use rustc_hash::FxHashMap;
+
+use super::{
+    error::{Error, Result},
+    rule::{Rule, RuleType},
+};
+
+const ROOT: u32 = 0;
+
+#[derive(Debug, Default)]
+struct Node {
+    full: Option<usize>,   // index of the `full:` rule ending at this node
+    domain: Option<usize>, // index of the `domain:` rule ending at this node
+}
+
+// Matches domain names the same way mosdns does: `full:` first, then the
+// most specific `domain:` suffix, then `regexp:` and `keyword:` in rule order.
+// `full:` and `domain:` rules are stored in a reversed label trie like
+// `RuleSet`, so a lookup costs one step per label of the queried name.
+#[derive(Debug)]
+pub struct Matcher {
+    rules: Vec<Rule>,
+    label_ids: FxHashMap<Box<str>, u32>,
+    nodes: Vec<Node>,
+    edges: FxHashMap<(u32, u32), u32>, // (parent node, label) -> child node
+    keywords: AhoCorasick,
+    keyword_rules: Vec<usize>,
+    regexes: RegexSet,
+    regex_rules: Vec<usize>,
+}
+
+impl Matcher {
+    pub fn new(rules: Vec<Rule>) -> Result<Self> {
+        let mut matcher = Matcher {
+            rules: vec![],
+            label_ids: FxHashMap::default(),
+            nodes: vec![Node::default()],
+            edges: FxHashMap::default(),
+            keywords: AhoCorasick::new(Vec::<&str>::new()).unwrap(),
+            keyword_rules: vec![],
+            regexes: RegexSet::empty(),
+            regex_rules: vec![],
+        };
+        let mut keywords = vec![];
+        let mut regexes = vec![];
+        for (index, rule) in rules.iter().enumerate() {
+            match rule.rule_type {
+                RuleType::Full | RuleType::Domain => {
+                    let node = matcher.insert(&normalize_domain(&rule.rule_content));
+                    let node = &mut matcher.nodes[node as usize];
+                    let slot = if rule.rule_type == RuleType::Full {
+                        &mut node.full
+                    } else {
+                        &mut node.domain
+                    };
+                    slot.get_or_insert(index);
+                }
+                RuleType::Keyword => {
+                    keywords.push(rule.rule_content.to_lowercase());
+                    matcher.keyword_rules.push(index);
+                }
+                RuleType::Regex => {
+                    regexes.push(rule.rule_content.as_str());
+                    matcher.regex_rules.push(index);
+                }
+            }
+        }
+        matcher.keywords = match AhoCorasick::new(keywords) {
+            Ok(keywords) => keywords,
+            Err(e) => return Err(Error::parse("keyword rules", e.to_string())),
+        };
+        matcher.regexes = match RegexSet::new(regexes) {
+            Ok(regexes) => regexes,
+            Err(e) => return Err(Error::parse("regexp rules", e.to_string())),
+        };
+        matcher.rules = rules;
+        Ok(matcher)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // The rule mosdns would match the domain name with, if any
+    pub fn match_domain(&self, domain: &str) -> Option<&Rule> {
+        let domain = normalize_domain(domain);
+        self.match_trie(&domain)
+            .or_else(|| {
+                self.regexes
+                    .matches(&domain)
+                    .iter()
+                    .next()
+                    .map(|i| self.regex_rules[i])
+            })
+            .or_else(|| {
+                self.keywords
+                    .find_overlapping_iter(domain.as_str())
+                    .map(|keyword| self.keyword_rules[keyword.pattern().as_usize()])
+                    .min()
+            })
+            .map(|index| &self.rules[index])
+    }
+
+    fn match_trie(&self, domain: &str) -> Option<usize> {
+        let mut current = ROOT;
+        let mut domain_match = None;
+        let mut labels = domain.rsplit('.');
+        let reached_end = loop {
+            let Some(label) = labels.next() else {
+                break true;
+            };
+            let next = self
+                .label_ids
+                .get(label)
+                .and_then(|label| self.edges.get(&(current, *label)));
+            match next {
+                Some(next) => current = *next,
+                None => break false,
+            }
+            domain_match = self.nodes[current as usize].domain.or(domain_match);
+        };
+        let full_match = if reached_end {
+            self.nodes[current as usize].full
+        } else {
+            None
+        };
+        full_match.or(domain_match)
+    }
+
+    fn insert(&mut self, domain: &str) -> u32 {
+        let mut current = ROOT;
+        for label in domain.rsplit('.') {
+            let label = match self.label_ids.get(label) {
+                Some(label) => *label,
+                None => {
+                    let id = self.label_ids.len() as u32;
+                    self.label_ids.insert(label.into(), id);
+                    id
+                }
+            };
+            current = match self.edges.get(&(current, label)) {
+                Some(child) => *child,
+                None => {
+                    self.nodes.push(Node::default());
+                    let child = (self.nodes.len() - 1) as u32;
+                    self.edges.insert((current, label), child);
+                    child
+                }
+            };
+        }
+        current
+    }
+}
+
+// mosdns compares domain names in lower case and without the trailing dot
+pub fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+#[test]
+fn test_matcher() {
+    let matcher = Matcher::new(vec![
+        Rule::new(RuleType::Keyword, "track".to_string()),
+        Rule::new(RuleType::Domain, "example.com".to_string()),
+        Rule::new(RuleType::Domain, "ads.example.com".to_string()),
+        Rule::new(RuleType::Full, "a.ads.example.com".to_string()),
+        Rule::new(RuleType::Regex, "^ad[0-9]+\\.".to_string()),
+        Rule::new(RuleType::Keyword, "ad".to_string()),
+    ])
+    .unwrap();
+    let matched = |domain: &str| matcher.match_domain(domain).map(|rule| rule.to_string());
+
+    assert_eq!(
+        matched("a.ads.example.com"),
+        Some("full:a.ads.example.com".to_string())
+    );
+    assert_eq!(
+        matched("A.Ads.Example.com."),
+        Some("full:a.ads.example.com".to_string())
+    );
+    assert_eq!(
+        matched("b.ads.example.com"),
+        Some("domain:ads.example.com".to_string())
+    );
+    assert_eq!(
+        matched("example.com"),
+        Some("domain:example.com".to_string())
+    );
+    // domain: only matches on label boundaries
+    assert_eq!(matched("badexample.com"), Some("keyword:ad".to_string()));
+    assert_eq!(matched("ad1.net"), Some("regexp:^ad[0-9]+\\.".to_string()));
+    assert_eq!(matched("adtracker.net"), Some("keyword:track".to_string()));
+    assert_eq!(matched("example.net"), None);
+}
@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, io::Write, time::SystemTime};
+
+use serde::Serialize;
+
+use super::{
+    download::SourceContent,
+    error::{Error, Result},
+    rule::{Provenance, Rule, RuleType},
+};
+
+pub struct OutputHeader<'a> {
+    pub generated_at: SystemTime,
+    pub sources: Vec<&'a SourceContent>,
+}
+
+impl<'a> OutputHeader<'a> {
+    pub fn new(sources: Vec<&'a SourceContent>) -> Self {
+        OutputHeader {
+            generated_at: SystemTime::now(),
+            sources,
+        }
+    }
+
+    // Render the header as mosdns comments, one `# ` line each
+    pub fn render(&self, rules: &[Rule]) -> String {
+        let mut header = String::new();
+        header.push_str(&format!(
+            "# Generated by {} {}\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        header.push_str(&format!(
+            "# Generated at: {}\n",
+            humantime::format_rfc3339_seconds(self.generated_at)
+        ));
+        for source in &self.sources {
+            let served_by = match &source.served_by {
+                Some(mirror) => format!(", from {}", mirror),
+                None => String::new(),
+            };
+            header.push_str(&format!(
+                "# Source: {} (fetched at {}, sha256 {}{})\n",
+                source.location,
+                humantime::format_rfc3339_seconds(source.fetched_at),
+                source.sha256,
+                served_by
+            ));
+        }
+        let count = |rule_type: RuleType| {
+            rules
+                .iter()
+                .filter(|rule| rule.rule_type == rule_type)
+                .count()
+        };
+        header.push_str(&format!(
+            "# Rules: {} (domain: {}, full: {}, keyword: {}, regex: {})\n",
+            rules.len(),
+            count(RuleType::Domain),
+            count(RuleType::Full),
+            count(RuleType::Keyword),
+            count(RuleType::Regex)
+        ));
+        header
+    }
+}
+
+pub fn write_rules(
+    path: &str,
+    rules: &[Rule],
+    header: Option<&OutputHeader>,
+    provenance_comments: bool,
+) -> Result<()> {
+    let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut writer = std::io::BufWriter::new(file);
+    let mut write = || -> std::io::Result<()> {
+        if let Some(header) = header {
+            writer.write_all(header.render(rules).as_bytes())?;
+        }
+        for rule in rules {
+            if provenance_comments && !rule.provenance.is_empty() {
+                writeln!(writer, "{} # {}", rule, rule.sources().join(", "))?;
+            } else {
+                writeln!(writer, "{}", rule)?;
+            }
+        }
+        writer.flush()
+    };
+    write().map_err(|e| Error::io(path, e))
+}
+
+#[derive(Serialize)]
+struct ProvenanceMap<'a> {
+    accept: BTreeMap<String, &'a [Provenance]>,
+    reject: BTreeMap<String, &'a [Provenance]>,
+}
+
+// Sidecar JSON mapping every output rule to the source lines it came from
+pub fn write_provenance_map(
+    path: &str,
+    accept_rules: &[Rule],
+    reject_rules: &[Rule],
+) -> Result<()> {
+    let provenance_map = ProvenanceMap {
+        accept: provenance_by_rule(accept_rules),
+        reject: provenance_by_rule(reject_rules),
+    };
+    let json = serde_json::to_string_pretty(&provenance_map).unwrap();
+    std::fs::write(path, json).map_err(|e| Error::io(path, e))
+}
+
+fn provenance_by_rule(rules: &[Rule]) -> BTreeMap<String, &[Provenance]> {
+    rules
+        .iter()
+        .map(|rule| (rule.to_string(), rule.provenance.as_slice()))
+        .collect()
+}
+
+#[test]
+fn test_render_header() {
+    let source = SourceContent::new("./reject.txt".to_string(), "domain:ads.com\n".to_string());
+    let header = OutputHeader {
+        generated_at: SystemTime::UNIX_EPOCH,
+        sources: vec![&source],
+    };
+    let rules = vec![
+        Rule::new(RuleType::Domain, "ads.com".to_string()),
+        Rule::new(RuleType::Full, "a.ads.net".to_string()),
+    ];
+    let rendered = header.render(&rules);
+    let lines = rendered.lines().collect::<Vec<&str>>();
+    assert_eq!(lines.len(), 4);
+    assert!(lines.iter().all(|line| line.starts_with("# ")));
+    assert_eq!(lines[1], "# Generated at: 1970-01-01T00:00:00Z");
+    assert!(lines[2].starts_with("# Source: ./reject.txt (fetched at "));
+    assert!(lines[2].ends_with(&format!("sha256 {})", source.sha256)));
+    assert_eq!(
+        lines[3],
+        "# Rules: 2 (domain: 1, full: 1, keyword: 0, regex: 0)"
+    );
+}
@@ -0,0 +1,158 @@
+use std::fmt::Display;
+
+use super::{config::Config, download::SourceContent, error::Result, matcher::Matcher, rule::Rule};
+
+pub struct QueryMatch {
+    pub accept_rule: bool,
+    pub location: String,
+    pub line_number: usize,
+    pub line: String,
+    pub rule: Rule,
+}
+
+pub enum Verdict {
+    Allowed(Rule),
+    Blocked(Rule),
+    NotMatched,
+}
+
+// Every source rule matching a domain name and the verdict of the merged rules
+pub struct QueryReport {
+    pub domain: String,
+    pub matches: Vec<QueryMatch>,
+    pub verdict: Verdict,
+}
+
+impl QueryReport {
+    pub fn new(config: &Config, sources: &[SourceContent], domain: &str) -> Result<Self> {
+        let mut matches = vec![];
+        for accept_rule in [true, false] {
+            for (rule_src, source) in config.rule_src.iter().zip(sources) {
+                if !rule_src.provides(accept_rule) {
+                    continue;
+                }
+                for line in source.lines(rule_src.src_type.format()) {
+                    let Some(rule) = line.parse(accept_rule) else {
+                        continue;
+                    };
+                    if rule.matches(domain) {
+                        matches.push(QueryMatch {
+                            accept_rule,
+                            location: line.location.to_string(),
+                            line_number: line.line_number,
+                            line: line.line.to_string(),
+                            rule,
+                        });
+                    }
+                }
+            }
+        }
+
+        // accept rules are checked before reject rules, like a whitelist in mosdns
+        let accept = Matcher::new(config.merge_rules(sources, true))?;
+        let reject = Matcher::new(config.merge_rules(sources, false))?;
+        let verdict = if let Some(rule) = accept.match_domain(domain) {
+            Verdict::Allowed(rule.clone())
+        } else if let Some(rule) = reject.match_domain(domain) {
+            Verdict::Blocked(rule.clone())
+        } else {
+            Verdict::NotMatched
+        };
+
+        Ok(QueryReport {
+            domain: domain.to_string(),
+            matches,
+            verdict,
+        })
+    }
+}
+
+impl Display for QueryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Query: {}", self.domain)?;
+        for accept_rule in [true, false] {
+            writeln!(
+                f,
+                "\n{} rules:",
+                if accept_rule { "Accept" } else { "Reject" }
+            )?;
+            let mut found = false;
+            for query_match in self.matches.iter() {
+                if query_match.accept_rule != accept_rule {
+                    continue;
+                }
+                found = true;
+                writeln!(
+                    f,
+                    "  {}:{}: {} ({})",
+                    query_match.location,
+                    query_match.line_number,
+                    query_match.line.trim(),
+                    query_match.rule
+                )?;
+            }
+            if !found {
+                writeln!(f, "  (none)")?;
+            }
+        }
+        match &self.verdict {
+            Verdict::Allowed(rule) => writeln!(f, "\nVerdict: allowed by {}", rule),
+            Verdict::Blocked(rule) => writeln!(f, "\nVerdict: blocked by {}", rule),
+            Verdict::NotMatched => writeln!(f, "\nVerdict: not matched"),
+        }
+    }
+}
+
+#[test]
+fn test_query() {
+    use super::config::RuleSrc;
+
+    let config = Config::new(vec![
+        RuleSrc::from_mosdns_file("allow.txt".to_string(), true, false),
+        RuleSrc::from_mosdns_file("block.txt".to_string(), false, false),
+    ]);
+    let sources = vec![
+        SourceContent::new("allow.txt".to_string(), "domain:ok.ads.com\n".to_string()),
+        SourceContent::new(
+            "block.txt".to_string(),
+            "# ads\ndomain:ads.com\nfull:tracker.net\n".to_string(),
+        ),
+    ];
+
+    // accept rules win over the reject rules matching too
+    let report = QueryReport::new(&config, &sources, "a.ok.ads.com").unwrap();
+    let matches: Vec<_> = report
+        .matches
+        .iter()
+        .map(|m| {
+            (
+                m.accept_rule,
+                m.location.as_str(),
+                m.line_number,
+                m.rule.to_string(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        matches,
+        [
+            (true, "allow.txt", 1, "domain:ok.ads.com".to_string()),
+            (false, "block.txt", 2, "domain:ads.com".to_string()),
+        ]
+    );
+    assert!(
+        matches!(&report.verdict, Verdict::Allowed(rule) if rule.to_string() == "domain:ok.ads.com")
+    );
+
+    let report = QueryReport::new(&config, &sources, "tracker.net").unwrap();
+    assert_eq!(report.matches.len(), 1);
+    assert_eq!(report.matches[0].line, "full:tracker.net");
+    assert!(
+        matches!(&report.verdict, Verdict::Blocked(rule) if rule.to_string() == "full:tracker.net")
+    );
+
+    // `full:` only matches the name itself
+    let report = QueryReport::new(&config, &sources, "a.tracker.net").unwrap();
+    assert!(report.matches.is_empty());
+    assert!(matches!(report.verdict, Verdict::NotMatched));
+}
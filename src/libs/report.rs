@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+use super::{
+    config::RuleSrc,
+    download::SourceContent,
+    error::{Error, Result},
+    rule::Rule,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EliminationReason {
+    Duplicate, // the same rule is already in the set
+    Subsumed,  // a `domain` rule covers it
+}
+
+// A rule dropped by the merge and the rule that made it redundant
+#[derive(Debug, Clone)]
+pub struct Eliminated {
+    pub rule: Rule,
+    pub kept: Rule,
+    pub reason: EliminationReason,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub eliminated: Vec<Eliminated>,
+}
+
+#[derive(Serialize)]
+struct EliminatedJson<'a> {
+    rule: String,
+    kept: String,
+    reason: EliminationReason,
+    sources: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct MergeReportJson<'a> {
+    eliminated: Vec<EliminatedJson<'a>>,
+    totals: BTreeMap<&'a str, usize>,
+}
+
+impl MergeReport {
+    // Number of eliminated rules contributed by each source
+    pub fn totals_per_source(&self) -> BTreeMap<&str, usize> {
+        let mut totals = BTreeMap::new();
+        for eliminated in &self.eliminated {
+            let sources = eliminated.rule.sources();
+            if sources.is_empty() {
+                *totals.entry("unknown").or_default() += 1;
+            }
+            for source in sources {
+                *totals.entry(source).or_default() += 1;
+            }
+        }
+        totals
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(MergeReportJson {
+            eliminated: self
+                .eliminated
+                .iter()
+                .map(|eliminated| EliminatedJson {
+                    rule: eliminated.rule.to_string(),
+                    kept: eliminated.kept.to_string(),
+                    reason: eliminated.reason,
+                    sources: eliminated.rule.sources(),
+                })
+                .collect(),
+            totals: self.totals_per_source(),
+        })
+        .unwrap()
+    }
+}
+
+impl Display for MergeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} rules eliminated", self.eliminated.len())?;
+        for eliminated in &self.eliminated {
+            let reason = match eliminated.reason {
+                EliminationReason::Duplicate => "duplicate of",
+                EliminationReason::Subsumed => "subsumed by",
+            };
+            write!(f, "  {} {} {}", eliminated.rule, reason, eliminated.kept)?;
+            let sources = eliminated
+                .rule
+                .provenance
+                .iter()
+                .map(|provenance| format!("{}:{}", provenance.source, provenance.line_number))
+                .collect::<Vec<String>>();
+            if sources.is_empty() {
+                writeln!(f)?;
+            } else {
+                writeln!(f, " ({})", sources.join(", "))?;
+            }
+        }
+        writeln!(f, "Eliminated rules per source:")?;
+        for (source, total) in self.totals_per_source() {
+            writeln!(f, "  {}: {}", source, total)?;
+        }
+        Ok(())
+    }
+}
+
+// Write the accept and reject reports, as JSON if the path ends with `.json`
+pub fn write_merge_report(
+    path: &str,
+    accept_report: &MergeReport,
+    reject_report: &MergeReport,
+) -> Result<()> {
+    let report = if path.ends_with(".json") {
+        serde_json::to_string_pretty(&serde_json::json!({
+            "accept": accept_report.to_json(),
+            "reject": reject_report.to_json(),
+        }))
+        .unwrap()
+    } else {
+        format!("# Accept\n{}\n# Reject\n{}", accept_report, reject_report)
+    };
+    std::fs::write(path, report).map_err(|e| Error::io(path, e))
+}
+
+#[derive(Serialize)]
+struct SourceRunJson<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    location: &'a str,
+    served_by: &'a str, // the mirror that served a remote source, else `location`
+    enabled: bool,
+    fetched_at: u64, // seconds since UNIX epoch
+    sha256: &'a str,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct RunReportJson<'a> {
+    sources: Vec<SourceRunJson<'a>>,
+}
+
+// Write where each source of the run came from as JSON
+pub fn write_run_report(path: &str, rule_src: &[RuleSrc], sources: &[SourceContent]) -> Result<()> {
+    let sources = rule_src
+        .iter()
+        .zip(sources)
+        .map(|(rule_src, source)| SourceRunJson {
+            name: rule_src.name.as_deref(),
+            location: &source.location,
+            served_by: source.served_by.as_deref().unwrap_or(&source.location),
+            enabled: rule_src.enabled,
+            fetched_at: source
+                .fetched_at
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            sha256: &source.sha256,
+            bytes: source.content.len(),
+        })
+        .collect();
+    let report = serde_json::to_string_pretty(&RunReportJson { sources }).unwrap();
+    std::fs::write(path, report).map_err(|e| Error::io(path, e))
+}
@@ -1,278 +1,262 @@
-use log::trace;
-use reqwest::header::HeaderMap;
-use std::{collections::HashMap, time::Duration};
-
-async fn async_getwebpage(
-    url: &str,
-    proxy_open: bool,
-    proxy_url: &str,
-    user_agent: &str,
-    cookie: &str,
-    headers: Option<HeaderMap>,
-) -> Result<(u16, HashMap<String, String>, String), ()> {
-    let mut client_builder = reqwest::Client::builder();
-    if proxy_open && proxy_url.len() != 0 {
-        client_builder = client_builder.proxy(if proxy_url.contains("://") {
-            if let Ok(value) = reqwest::Proxy::all(proxy_url) {
-                value
-            } else {
-                return Err(());
-            }
-        } else {
-            if let Ok(value) = reqwest::Proxy::all(format!("socks5://{}", proxy_url)) {
-                value
-            } else {
-                return Err(());
-            }
-        });
+use super::error::{Error, Result};
+use log::{trace, warn};
+use reqwest::header::{HeaderMap, COOKIE, USER_AGENT};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+// Settings of the shared HTTP client, the `http` section of the config.
+// Sources can override them with their own `http` section, see `SourceHttpOptions`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>, // `scheme://host:port`, `host:port` is taken as socks5
+    pub timeout: u64, // seconds, for the whole request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>, // seconds
+    pub user_agent: String,
+    pub compression: bool, // accept brotli, gzip and deflate encoded responses
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<HttpAuth>,
+    pub retry: RetryPolicy,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            proxy: None,
+            timeout: 20,
+            connect_timeout: None,
+            user_agent: "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3".to_string(),
+            compression: true,
+            headers: BTreeMap::new(),
+            cookie: None,
+            auth: None,
+            retry: RetryPolicy::default(),
+        }
     }
-    let mut client = if let Ok(value) = client_builder
-        .brotli(true)
-        .gzip(true)
-        .deflate(true)
-        .timeout(Duration::from_secs(20))
-        .user_agent(user_agent)
-        .build()
-    {
-        value
-    } else {
-        return Err(());
+}
+
+impl HttpOptions {
+    // These options with the ones a source sets, headers are merged by name
+    pub fn merged(&self, overrides: &SourceHttpOptions) -> HttpOptions {
+        let mut headers = self.headers.clone();
+        headers.extend(overrides.headers.clone());
+        HttpOptions {
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+            timeout: overrides.timeout.unwrap_or(self.timeout),
+            connect_timeout: overrides.connect_timeout.or(self.connect_timeout),
+            user_agent: overrides
+                .user_agent
+                .clone()
+                .unwrap_or_else(|| self.user_agent.clone()),
+            compression: overrides.compression.unwrap_or(self.compression),
+            headers,
+            cookie: overrides.cookie.clone().or_else(|| self.cookie.clone()),
+            auth: overrides.auth.clone().or_else(|| self.auth.clone()),
+            retry: overrides
+                .retry
+                .clone()
+                .unwrap_or_else(|| self.retry.clone()),
+        }
     }
-    .get(url);
-    if let Some(value) = headers {
-        client = client
-            .headers(value)
-            .header("cookie", cookie)
-            .header("Accept-Encoding", "gzip, deflate, br");
+}
+
+// The `http` section of a source, every field left out is inherited from
+// the `http` section of the config
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceHttpOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<bool>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<HttpAuth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>, // replaces the global policy as a whole
+}
+
+// When and how long to wait before trying a request again. Only transient
+// failures are retried: network errors, timeouts, 429 and 5xx responses.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    pub max_attempts: u32, // attempts in total, 1 disables retrying
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64, // also the longest `Retry-After` waited for
+    pub jitter: bool,      // wait a random 50-100% of the delay
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            jitter: true,
+        }
     }
-    let rsp_raw_data = if let Ok(value) = client.send().await {
-        value
-    } else {
-        return Err(());
-    };
-    // match rsp_raw_data.status().as_u16() {
-    //     404 | 429 => return Err(()),
-    //     _ => (),
-    // }
-    let status = rsp_raw_data.status().as_u16();
-    let rsp_headers: HashMap<String, String> = rsp_raw_data
-        .headers()
-        .iter()
-        .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
-        .collect();
-    let rsp_body = if let Ok(value) = rsp_raw_data.text().await {
-        value
-    } else {
-        return Err(());
-    };
-    Ok((status, rsp_headers, rsp_body))
 }
 
-async fn async_deletewebpage(
-    url: &str,
-    proxy_open: bool,
-    proxy_url: &str,
-    user_agent: &str,
-    cookie: &str,
-    headers: Option<HeaderMap>,
-) -> Result<(u16, HashMap<String, String>, String), ()> {
-    let mut client_builder = reqwest::Client::builder();
-    if proxy_open && proxy_url.len() != 0 {
-        client_builder = client_builder.proxy(if proxy_url.contains("://") {
-            if let Ok(value) = reqwest::Proxy::all(proxy_url) {
-                value
-            } else {
-                return Err(());
-            }
+impl RetryPolicy {
+    // Delay before the attempt after `attempt` (1 based), doubling every time
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(1 << attempt.saturating_sub(1).min(32))
+            .min(self.max_delay_ms);
+        let delay = Duration::from_millis(delay);
+        if self.jitter {
+            delay.mul_f64(0.5 + fastrand::f64() * 0.5)
         } else {
-            if let Ok(value) = reqwest::Proxy::all(format!("socks5://{}", proxy_url)) {
-                value
-            } else {
-                return Err(());
-            }
-        });
-    }
-    let mut client = if let Ok(value) = client_builder
-        .brotli(true)
-        .gzip(true)
-        .deflate(true)
-        .timeout(Duration::from_secs(20))
-        .user_agent(user_agent)
-        .build()
-    {
-        value
-    } else {
-        return Err(());
+            delay
+        }
     }
-    .delete(url);
-    if let Some(value) = headers {
-        client = client
-            .headers(value)
-            .header("cookie", cookie)
-            .header("Accept-Encoding", "gzip, deflate, br");
+}
+
+// `Retry-After` as seconds or as an HTTP date
+fn retry_after(headers: &HashMap<String, String>) -> Option<Duration> {
+    let value = headers.get("retry-after")?.trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+        }
     }
-    let rsp_raw_data = if let Ok(value) = client.send().await {
-        value
-    } else {
-        return Err(());
-    };
-    // match rsp_raw_data.status().as_u16() {
-    //     404 | 429 => return Err(()),
-    //     _ => (),
-    // }
-    let status = rsp_raw_data.status().as_u16();
-    let rsp_headers: HashMap<String, String> = rsp_raw_data
-        .headers()
-        .iter()
-        .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
-        .collect();
-    let rsp_body = if let Ok(value) = rsp_raw_data.text().await {
-        value
-    } else {
-        return Err(());
-    };
-    Ok((status, rsp_headers, rsp_body))
 }
 
-async fn async_postwebpage(
-    url: &str,
-    content: &str,
-    proxy_open: bool,
-    proxy_url: &str,
-    user_agent: &str,
-    cookie: &str,
-    headers: Option<HeaderMap>,
-) -> Result<(u16, HashMap<String, String>, String), ()> {
-    let mut client_builder = reqwest::Client::builder();
-    if proxy_open && proxy_url.len() != 0 {
-        client_builder = client_builder.proxy(if proxy_url.contains("://") {
-            if let Ok(value) = reqwest::Proxy::all(proxy_url) {
-                value
-            } else {
-                return Err(());
-            }
-        } else {
-            if let Ok(value) = reqwest::Proxy::all(format!("socks5://{}", proxy_url)) {
-                value
-            } else {
-                return Err(());
-            }
-        });
+// `{"basic": {"username": "u", "password": "p"}}` or `{"bearer": "token"}`
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpAuth {
+    Basic {
+        username: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+// Keep credentials out of logs such as the `trace!` of every request
+impl Debug for HttpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpAuth::Basic { username, .. } => write!(f, "Basic({}, ***)", username),
+            HttpAuth::Bearer(_) => write!(f, "Bearer(***)"),
+        }
     }
-    let mut client = if let Ok(value) = client_builder
-        .brotli(true)
-        .gzip(true)
-        .deflate(true)
-        .timeout(Duration::from_secs(20))
-        .user_agent(user_agent)
-        .build()
-    {
-        value
-    } else {
-        return Err(());
+}
+
+// Settings reqwest only takes when building a client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    proxy: Option<String>,
+    connect_timeout: Option<u64>,
+    compression: bool,
+}
+
+// Connection pools shared by every request, one per proxy and client level
+// setting in use. Cloning is cheap and keeps sharing the pools.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    clients: Arc<Mutex<HashMap<ClientKey, reqwest::Client>>>,
+    options: HttpOptions,
+}
+
+impl HttpClient {
+    pub fn new(options: &HttpOptions) -> Result<Self> {
+        let client = HttpClient {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            options: options.clone(),
+        };
+        // fail early on an invalid proxy
+        client.reqwest_client(options.proxy.as_deref())?;
+        Ok(client)
     }
-    .post(url)
-    .body(content.to_owned());
-    if let Some(value) = headers {
-        client = client
-            .headers(value)
-            .header("cookie", cookie)
-            .header("Accept-Encoding", "gzip, deflate, br");
+
+    // A client sending requests with other options, sharing the connection pools
+    pub fn with_options(&self, options: HttpOptions) -> HttpClient {
+        HttpClient {
+            clients: self.clients.clone(),
+            options,
+        }
     }
-    let rsp_raw_data = if let Ok(value) = client.send().await {
-        value
-    } else {
-        return Err(());
-    };
-    // match rsp_raw_data.status().as_u16() {
-    //     404 | 429 => return Err(()),
-    //     _ => (),
-    // }
-    let status = rsp_raw_data.status().as_u16();
-
-    let rsp_headers: HashMap<String, String> = rsp_raw_data
-        .headers()
-        .iter()
-        .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
-        .collect();
-    let rsp_body = if let Ok(value) = rsp_raw_data.text().await {
-        value
-    } else {
-        return Err(());
-    };
-    Ok((status, rsp_headers, rsp_body))
-}
 
-async fn async_patchwebpage(
-    url: &str,
-    content: &str,
-    proxy_open: bool,
-    proxy_url: &str,
-    user_agent: &str,
-    cookie: &str,
-    headers: Option<HeaderMap>,
-) -> Result<(u16, HashMap<String, String>, String), ()> {
-    let mut client_builder = reqwest::Client::builder();
-    if proxy_open && proxy_url.len() != 0 {
-        client_builder = client_builder.proxy(if proxy_url.contains("://") {
-            if let Ok(value) = reqwest::Proxy::all(proxy_url) {
-                value
-            } else {
-                return Err(());
-            }
-        } else {
-            if let Ok(value) = reqwest::Proxy::all(format!("socks5://{}", proxy_url)) {
-                value
-            } else {
-                return Err(());
-            }
-        });
+    pub fn options(&self) -> &HttpOptions {
+        &self.options
     }
-    let mut client = if let Ok(value) = client_builder
-        .brotli(true)
-        .gzip(true)
-        .deflate(true)
-        .timeout(Duration::from_secs(20))
-        .user_agent(user_agent)
-        .build()
-    {
-        value
-    } else {
-        return Err(());
+
+    // The client to request `url` with on behalf of the source at
+    // `source_url`. Its auth, cookie and headers are only meant for the host
+    // of the source, other hosts such as public mirrors don't get them.
+    pub fn for_url(&self, url: &str, source_url: &str) -> HttpClient {
+        if same_origin(url, source_url) {
+            return self.clone();
+        }
+        self.with_options(HttpOptions {
+            headers: BTreeMap::new(),
+            cookie: None,
+            auth: None,
+            ..self.options.clone()
+        })
     }
-    .patch(url)
-    .body(content.to_owned());
-    if let Some(value) = headers {
-        client = client
-            .headers(value)
-            .header("cookie", cookie)
-            .header("Accept-Encoding", "gzip, deflate, br");
+
+    fn reqwest_client(&self, proxy: Option<&str>) -> Result<reqwest::Client> {
+        let key = ClientKey {
+            proxy: proxy.filter(|proxy| !proxy.is_empty()).map(str::to_string),
+            connect_timeout: self.options.connect_timeout,
+            compression: self.options.compression,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let mut client_builder = reqwest::Client::builder()
+            .brotli(key.compression)
+            .gzip(key.compression)
+            .deflate(key.compression);
+        if let Some(connect_timeout) = key.connect_timeout {
+            client_builder = client_builder.connect_timeout(Duration::from_secs(connect_timeout));
+        }
+        if let Some(proxy_url) = &key.proxy {
+            // socks5h:// resolves names through the proxy
+            let proxy = if proxy_url.contains("://") {
+                reqwest::Proxy::all(proxy_url)
+            } else {
+                reqwest::Proxy::all(format!("socks5://{}", proxy_url))
+            };
+            client_builder = client_builder.proxy(proxy.map_err(|e| Error::network(proxy_url, e))?);
+        }
+        let client = client_builder
+            .build()
+            .map_err(|e| Error::network("HTTP client", e))?;
+        clients.insert(key, client.clone());
+        Ok(client)
     }
-    let rsp_raw_data = if let Ok(value) = client.send().await {
-        value
-    } else {
-        return Err(());
-    };
-    // match rsp_raw_data.status().as_u16() {
-    //     404 | 429 => return Err(()),
-    //     _ => (),
-    // }
-    let status = rsp_raw_data.status().as_u16();
-
-    let rsp_headers: HashMap<String, String> = rsp_raw_data
-        .headers()
-        .iter()
-        .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
-        .collect();
-    let rsp_body = if let Ok(value) = rsp_raw_data.text().await {
-        value
-    } else {
-        return Err(());
-    };
-    Ok((status, rsp_headers, rsp_body))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RequestStructure {
     pub mathod: RequestMethod,
     pub url: String,
@@ -283,20 +267,7 @@ pub struct RequestStructure {
     pub cookie: Option<String>,
 }
 
-impl Clone for RequestStructure {
-    fn clone(&self) -> Self {
-        Self {
-            mathod: self.mathod.clone(),
-            url: self.url.clone(),
-            content: self.content.clone(),
-            headers: self.headers.clone(),
-            proxy: self.proxy.clone(),
-            user_agent: self.user_agent.clone(),
-            cookie: self.cookie.clone(),
-        }
-    }
-}
-
+// Fields left `None` are taken from the options of the client
 impl RequestStructure {
     pub fn new(
         mathod: RequestMethod,
@@ -330,60 +301,199 @@ impl RequestStructure {
         }
     }
 
-    pub async fn execute(&self) -> Result<(u16, HashMap<String, String>, String), ()> {
+    // Status, response headers and body. A status other than 2xx is not an
+    // error here, callers decide what they accept.
+    pub async fn execute(
+        &self,
+        client: &HttpClient,
+    ) -> Result<(u16, HashMap<String, String>, String)> {
+        let (status, headers, body) = self.execute_bytes(client).await?;
+        Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    // `execute` with the body as it was received, such as a compressed file
+    pub async fn execute_bytes(
+        &self,
+        client: &HttpClient,
+    ) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
         trace!("RequestStructure execute: {:?}", self);
-        match self.mathod {
-            RequestMethod::GET => {
-                async_getwebpage(
-                    &self.url,
-                    self.proxy.is_some(),
-                    &self.proxy.clone().unwrap_or_default(),
-                    &self.user_agent.clone().unwrap_or_default(),
-                    &self.cookie.clone().unwrap_or_default(),
-                    self.headers.clone(),
-                )
-                .await
+        let options = client.options();
+        let proxy = self.proxy.as_deref().or(options.proxy.as_deref());
+        let mut request = client
+            .reqwest_client(proxy)?
+            .request(self.mathod.as_reqwest(), &self.url)
+            .timeout(Duration::from_secs(options.timeout));
+        if matches!(self.mathod, RequestMethod::POST | RequestMethod::PATCH) {
+            request = request.body(self.content.clone());
+        }
+        for (name, value) in &options.headers {
+            request = request.header(name, value);
+        }
+        if let Some(headers) = &self.headers {
+            request = request.headers(headers.clone());
+        }
+        let user_agent = self.user_agent.as_ref().unwrap_or(&options.user_agent);
+        request = request.header(USER_AGENT, user_agent);
+        if let Some(cookie) = self.cookie.as_ref().or(options.cookie.as_ref()) {
+            request = request.header(COOKIE, cookie);
+        }
+        request = match &options.auth {
+            Some(HttpAuth::Basic { username, password }) => {
+                request.basic_auth(username, password.as_ref())
             }
-            RequestMethod::POST => {
-                async_postwebpage(
-                    &self.url,
-                    &self.content,
-                    self.proxy.is_some(),
-                    &self.proxy.clone().unwrap_or_default(),
-                    &self.user_agent.clone().unwrap_or_default(),
-                    &self.cookie.clone().unwrap_or_default(),
-                    self.headers.clone(),
-                )
-                .await
+            Some(HttpAuth::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Error::network(&self.url, e))?;
+        let status = response.status().as_u16();
+        let headers: HashMap<String, String> = response
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
+            .collect();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| Error::network(&self.url, e))?;
+        Ok((status, headers, body.to_vec()))
+    }
+}
+
+impl RequestStructure {
+    // `execute_bytes` with the retry policy of the client. Transient failures
+    // are tried again after the backoff or the `Retry-After` of the response,
+    // and any status but 2xx ends in `Error::HttpStatus`.
+    pub async fn execute_with_retry(
+        &self,
+        client: &HttpClient,
+    ) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
+        let policy = &client.options().retry;
+        let max_attempts = policy.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let (error, retry_after) = match self.execute_bytes(client).await {
+                Ok(response) if (200..300).contains(&response.0) => return Ok(response),
+                Ok((status, headers, _)) => (
+                    Error::HttpStatus {
+                        url: self.url.clone(),
+                        status,
+                    },
+                    retry_after(&headers),
+                ),
+                Err(e) => (e, None),
+            };
+            if attempt >= max_attempts || !error.is_transient() {
+                return Err(error);
             }
-            RequestMethod::PATCH => {
-                async_patchwebpage(
-                    &self.url,
-                    &self.content,
-                    self.proxy.is_some(),
-                    &self.proxy.clone().unwrap_or_default(),
-                    &self.user_agent.clone().unwrap_or_default(),
-                    &self.cookie.clone().unwrap_or_default(),
-                    self.headers.clone(),
-                )
-                .await
+            let delay = match retry_after {
+                Some(delay) if delay > Duration::from_millis(policy.max_delay_ms) => {
+                    warn!(
+                        "{}, the server asks to retry in {:?}, longer than allowed",
+                        error.chain(),
+                        delay
+                    );
+                    return Err(error);
+                }
+                Some(delay) => delay,
+                None => policy.backoff(attempt),
+            };
+            warn!(
+                "Attempt {}/{} failed: {}, retrying in {:?}",
+                attempt,
+                max_attempts,
+                error.chain(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+// Same scheme, host and port, unparsable URLs are never the same
+fn same_origin(url: &str, other: &str) -> bool {
+    match (reqwest::Url::parse(url), reqwest::Url::parse(other)) {
+        (Ok(url), Ok(other)) => {
+            url.scheme() == other.scheme()
+                && url.host_str() == other.host_str()
+                && url.port_or_known_default() == other.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+// How the URLs of a source with mirrors are tried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorStrategy {
+    #[default]
+    Ordered, // one after the other until one succeeds
+    Race, // all at once, the first success wins and the others are cancelled
+}
+
+impl MirrorStrategy {
+    pub fn is_ordered(&self) -> bool {
+        *self == MirrorStrategy::Ordered
+    }
+}
+
+// GET the first of `urls` that succeeds, each with the retry policy of the
+// client. The first URL is the source's own, the credentials of the client
+// are only sent to its host, see `HttpClient::for_url`. Returns the URL that
+// served the body and the body, or the error of the last URL that failed.
+pub async fn get_from_mirrors(
+    client: &HttpClient,
+    urls: &[String],
+    strategy: MirrorStrategy,
+) -> Result<(String, Vec<u8>)> {
+    let request = |url: &String| {
+        RequestStructure::new_default(RequestMethod::GET, url.to_string(), "".to_owned())
+    };
+    let client_for = |url: &String| client.for_url(url, &urls[0]);
+    let mut last_error = None;
+    match strategy {
+        MirrorStrategy::Ordered => {
+            for url in urls {
+                match request(url).execute_with_retry(&client_for(url)).await {
+                    Ok(response) => return Ok((url.to_string(), response.2)),
+                    Err(e) => {
+                        if urls.len() > 1 {
+                            warn!("Mirror failed: {}", e.chain());
+                        }
+                        last_error = Some(e);
+                    }
+                }
             }
-            RequestMethod::DELETE => {
-                async_deletewebpage(
-                    &self.url,
-                    self.proxy.is_some(),
-                    &self.proxy.clone().unwrap_or_default(),
-                    &self.user_agent.clone().unwrap_or_default(),
-                    &self.cookie.clone().unwrap_or_default(),
-                    self.headers.clone(),
-                )
-                .await
+        }
+        MirrorStrategy::Race => {
+            let mut requests = tokio::task::JoinSet::new();
+            for url in urls {
+                let (request, client) = (request(url), client_for(url));
+                requests.spawn(async move {
+                    let response = request.execute_with_retry(&client).await;
+                    (request.url, response)
+                });
+            }
+            // dropping the set aborts the requests still running
+            while let Some(joined) = requests.join_next().await {
+                match joined {
+                    Ok((url, Ok(response))) => return Ok((url, response.2)),
+                    Ok((_, Err(e))) => {
+                        warn!("Mirror failed: {}", e.chain());
+                        last_error = Some(e);
+                    }
+                    Err(e) => warn!("Mirror request panicked: {}", e),
+                }
             }
         }
     }
+    Err(last_error.unwrap_or_else(|| Error::Config("no URL to download from".to_string())))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RequestMethod {
     GET,
     POST,
@@ -391,13 +501,140 @@ pub enum RequestMethod {
     DELETE,
 }
 
-impl Clone for RequestMethod {
-    fn clone(&self) -> Self {
+impl RequestMethod {
+    fn as_reqwest(&self) -> reqwest::Method {
         match self {
-            RequestMethod::GET => RequestMethod::GET,
-            RequestMethod::POST => RequestMethod::POST,
-            RequestMethod::PATCH => RequestMethod::PATCH,
-            RequestMethod::DELETE => RequestMethod::DELETE,
+            RequestMethod::GET => reqwest::Method::GET,
+            RequestMethod::POST => reqwest::Method::POST,
+            RequestMethod::PATCH => reqwest::Method::PATCH,
+            RequestMethod::DELETE => reqwest::Method::DELETE,
         }
     }
 }
+
+#[test]
+fn test_merged_options() {
+    let global = HttpOptions {
+        proxy: Some("socks5h://127.0.0.1:1080".to_string()),
+        headers: BTreeMap::from([
+            ("X-Team".to_string(), "dns".to_string()),
+            ("X-Site".to_string(), "a".to_string()),
+        ]),
+        auth: Some(HttpAuth::Bearer("global".to_string())),
+        ..HttpOptions::default()
+    };
+    let merged = global.merged(&SourceHttpOptions {
+        timeout: Some(5),
+        headers: BTreeMap::from([("X-Team".to_string(), "ops".to_string())]),
+        ..SourceHttpOptions::default()
+    });
+    assert_eq!(merged.proxy, global.proxy);
+    assert_eq!(merged.timeout, 5);
+    assert_eq!(merged.user_agent, global.user_agent);
+    assert_eq!(merged.headers["X-Team"], "ops");
+    assert_eq!(merged.headers["X-Site"], "a");
+    assert_eq!(merged.auth, global.auth);
+    assert_eq!(format!("{:?}", merged.auth.unwrap()), "Bearer(***)");
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        jitter: false,
+        max_delay_ms: 5000,
+        ..RetryPolicy::default()
+    };
+    let delays: Vec<u128> = (1..=5).map(|n| policy.backoff(n).as_millis()).collect();
+    assert_eq!(delays, vec![1000, 2000, 4000, 5000, 5000]);
+    let jittered = RetryPolicy::default().backoff(2);
+    assert!(jittered >= Duration::from_millis(1000) && jittered <= Duration::from_millis(2000));
+
+    let headers = HashMap::from([("retry-after".to_string(), "7".to_string())]);
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+    let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+    let headers = HashMap::from([("retry-after".to_string(), date)]);
+    assert!(retry_after(&headers).unwrap() > Duration::from_secs(100));
+    assert_eq!(retry_after(&HashMap::new()), None);
+
+    let status = |status| Error::HttpStatus {
+        url: String::new(),
+        status,
+    };
+    assert!(status(429).is_transient() && status(503).is_transient());
+    assert!(!status(404).is_transient());
+}
+
+#[tokio::test]
+async fn test_get_from_mirrors() {
+    use std::io::{Read, Write};
+
+    // answers 404 on /missing and `rules` otherwise, keeping every request head
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let received = requests.clone();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut head = vec![];
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            let head = String::from_utf8_lossy(&head).to_lowercase();
+            let status = if head.starts_with("get /missing") {
+                "404 Not Found"
+            } else {
+                "200 OK"
+            };
+            received.lock().unwrap().push(head);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-length: 5\r\nconnection: close\r\n\r\nrules",
+                status
+            );
+        }
+    });
+    // nothing listens on a port just released
+    let dead = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/rules.txt", listener.local_addr().unwrap())
+    };
+
+    let client = HttpClient::new(&HttpOptions {
+        auth: Some(HttpAuth::Bearer("secret".to_string())),
+        retry: RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        },
+        ..HttpOptions::default()
+    })
+    .unwrap();
+    let mirror = format!("{}/rules.txt", server);
+
+    // a mirror on the source's own host gets the credentials
+    let urls = [format!("{}/missing", server), mirror.clone()];
+    let (served_by, body) = get_from_mirrors(&client, &urls, MirrorStrategy::Ordered)
+        .await
+        .unwrap();
+    assert_eq!(
+        (served_by.as_str(), &body[..]),
+        (mirror.as_str(), &b"rules"[..])
+    );
+    assert!(requests.lock().unwrap()[1].contains("authorization: bearer secret"));
+
+    // one on another host doesn't, whether tried in order or raced
+    for strategy in [MirrorStrategy::Ordered, MirrorStrategy::Race] {
+        requests.lock().unwrap().clear();
+        let urls = [dead.clone(), mirror.clone()];
+        let (served_by, _) = get_from_mirrors(&client, &urls, strategy).await.unwrap();
+        assert_eq!(served_by, mirror);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].contains("authorization"));
+    }
+
+    let error = get_from_mirrors(&client, &[dead], MirrorStrategy::Ordered)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::Network { .. }));
+}
@@ -1,8 +1,27 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::matcher::normalize_domain;
+
+#[derive(Debug, Clone)]
 pub struct Rule {
     pub rule_type: RuleType,
     pub rule_content: String,
+    pub provenance: Vec<Provenance>, // empty unless tracked, see `Config::tracks_provenance`
 }
 
+// Where a rule came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Provenance {
+    pub source: Arc<str>,
+    pub line_number: usize,
+    pub line: String,
+}
+
+// Declared in the alphabetical order of the mosdns prefixes, which
+// `OutputOrder::compare` relies on
+#[derive(Debug, Clone, Copy)]
 pub enum RuleType {
     Domain,
     Full,
@@ -15,6 +34,40 @@ impl Rule {
         Rule {
             rule_type,
             rule_content,
+            provenance: vec![],
+        }
+    }
+
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.provenance.push(provenance);
+        self
+    }
+
+    // Contributing sources, without duplicates and in order of appearance
+    pub fn sources(&self) -> Vec<&str> {
+        let mut sources: Vec<&str> = vec![];
+        for provenance in &self.provenance {
+            if !sources.contains(&provenance.source.as_ref()) {
+                sources.push(&provenance.source);
+            }
+        }
+        sources
+    }
+
+    // Whether this single rule matches the domain name, see `Matcher` for
+    // matching against many rules
+    pub fn matches(&self, domain: &str) -> bool {
+        let domain = normalize_domain(domain);
+        match self.rule_type {
+            RuleType::Full => domain == self.rule_content.to_lowercase(),
+            RuleType::Domain => {
+                let rule_content = self.rule_content.to_lowercase();
+                domain == rule_content || domain.ends_with(&format!(".{}", rule_content))
+            }
+            RuleType::Keyword => domain.contains(&self.rule_content.to_lowercase()),
+            RuleType::Regex => regex::Regex::new(&self.rule_content)
+                .map(|regex| regex.is_match(&domain))
+                .unwrap_or(false),
         }
     }
 }
@@ -27,13 +80,13 @@ impl PartialEq for Rule {
 
 impl PartialEq for RuleType {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
+        matches!(
+            (self, other),
             (RuleType::Domain, RuleType::Domain)
-            | (RuleType::Full, RuleType::Full)
-            | (RuleType::Keyword, RuleType::Keyword)
-            | (RuleType::Regex, RuleType::Regex) => true,
-            _ => false,
-        }
+                | (RuleType::Full, RuleType::Full)
+                | (RuleType::Keyword, RuleType::Keyword)
+                | (RuleType::Regex, RuleType::Regex)
+        )
     }
 }
 
@@ -49,7 +102,7 @@ impl std::fmt::Display for RuleType {
             RuleType::Domain => write!(f, "domain"),
             RuleType::Full => write!(f, "full"),
             RuleType::Keyword => write!(f, "keyword"),
-            RuleType::Regex => write!(f, "regex"),
+            RuleType::Regex => write!(f, "regexp"),
         }
     }
 }
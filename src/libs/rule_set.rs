@@ -0,0 +1,590 @@
+use rustc_hash::FxHashMap;
+
+use super::{
+    report::{Eliminated, EliminationReason, MergeReport},
+    rule::{Provenance, Rule, RuleType},
+};
+
+const ROOT: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Intermediate, // 中间节点, 不是规则
+    Full,         // full: 只匹配该节点
+    Domain,       // domain: 匹配该节点及所有子节点
+}
+
+#[derive(Debug)]
+struct Node {
+    label: u32,
+    parent: u32,
+    kind: NodeKind,
+    children: Vec<u32>,
+}
+
+// Domain trie keyed by reversed labels, e.g. `a.example.com` is stored as
+// root -> com -> example -> a. Nodes live in one arena and refer to each
+// other by index, labels are interned so each distinct label is stored once.
+#[derive(Debug)]
+pub struct RuleSet {
+    labels: Vec<Box<str>>,
+    label_ids: FxHashMap<Box<str>, u32>,
+    nodes: Vec<Node>,
+    edges: FxHashMap<(u32, u32), u32>, // (parent node, label) -> child node
+    free_nodes: Vec<u32>,
+    provenance: FxHashMap<u32, Vec<Provenance>>, // only for rules inserted with provenance
+    report: Option<MergeReport>,
+    len: usize,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        RuleSet {
+            labels: vec![],
+            label_ids: FxHashMap::default(),
+            nodes: vec![Node {
+                label: u32::MAX,
+                parent: ROOT,
+                kind: NodeKind::Intermediate,
+                children: vec![],
+            }],
+            edges: FxHashMap::default(),
+            free_nodes: vec![],
+            provenance: FxHashMap::default(),
+            report: None,
+            len: 0,
+        }
+    }
+
+    // Record every rule eliminated by `insert`, see `take_report`
+    pub fn with_report() -> Self {
+        RuleSet {
+            report: Some(MergeReport::default()),
+            ..Self::new()
+        }
+    }
+
+    pub fn take_report(&mut self) -> Option<MergeReport> {
+        self.report.as_mut().map(std::mem::take)
+    }
+
+    // Number of `domain` and `full` rules in the set
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Insert a rule, returns false if the rule is already covered by the set.
+    // A `domain` rule removes every rule below it. Keyword and Regex rules
+    // can't be stored in the trie and are rejected.
+    pub fn insert(&mut self, rule: &Rule) -> bool {
+        let kind = match rule.rule_type {
+            RuleType::Domain => NodeKind::Domain,
+            RuleType::Full => NodeKind::Full,
+            RuleType::Keyword | RuleType::Regex => return false,
+        };
+        let mut current = ROOT;
+        for (depth, label) in rule.rule_content.rsplit('.').enumerate() {
+            if self.nodes[current as usize].kind == NodeKind::Domain {
+                self.record(rule, current, depth, EliminationReason::Subsumed);
+                return false;
+            }
+            let label = self.intern(label);
+            current = match self.edges.get(&(current, label)) {
+                Some(child) => *child,
+                None => self.add_node(current, label),
+            };
+        }
+        let depth = rule.rule_content.split('.').count();
+        let node = &mut self.nodes[current as usize];
+        let inserted = match (node.kind, kind) {
+            (NodeKind::Domain, NodeKind::Domain) | (NodeKind::Full, NodeKind::Full) => {
+                // the same rule again, only remember where else it came from
+                self.record(rule, current, depth, EliminationReason::Duplicate);
+                self.add_provenance(current, rule);
+                return false;
+            }
+            (NodeKind::Domain, NodeKind::Full) => {
+                self.record(rule, current, depth, EliminationReason::Subsumed);
+                false
+            }
+            (NodeKind::Intermediate, NodeKind::Full) => {
+                node.kind = NodeKind::Full;
+                self.len += 1;
+                true
+            }
+            (previous, _) => {
+                if self.report.is_some() {
+                    self.record_subtree(rule, current, previous == NodeKind::Full);
+                }
+                let node = &mut self.nodes[current as usize];
+                node.kind = NodeKind::Domain;
+                if previous == NodeKind::Intermediate {
+                    self.len += 1;
+                }
+                self.provenance.remove(&current);
+                self.remove_children(current);
+                true
+            }
+        };
+        if inserted {
+            self.add_provenance(current, rule);
+        }
+        inserted
+    }
+
+    // Remove exactly this rule, returns false if it isn't in the set. Rules
+    // that were dropped when a `domain` rule covered them are not restored.
+    pub fn remove(&mut self, rule: &Rule) -> bool {
+        let kind = match rule.rule_type {
+            RuleType::Domain => NodeKind::Domain,
+            RuleType::Full => NodeKind::Full,
+            RuleType::Keyword | RuleType::Regex => return false,
+        };
+        let Some(mut current) = self.find(&rule.rule_content) else {
+            return false;
+        };
+        if self.nodes[current as usize].kind != kind {
+            return false;
+        }
+        self.nodes[current as usize].kind = NodeKind::Intermediate;
+        self.provenance.remove(&current);
+        self.len -= 1;
+        // prune intermediate nodes which no longer lead to a rule
+        while current != ROOT {
+            let node = &self.nodes[current as usize];
+            if node.kind != NodeKind::Intermediate || !node.children.is_empty() {
+                break;
+            }
+            let (parent, label) = (node.parent, node.label);
+            self.edges.remove(&(parent, label));
+            self.nodes[parent as usize]
+                .children
+                .retain(|child| *child != current);
+            self.free_nodes.push(current);
+            current = parent;
+        }
+        true
+    }
+
+    // Whether the domain name is matched by a rule in the set
+    pub fn contains(&self, domain: &str) -> bool {
+        let mut current = ROOT;
+        for label in domain.rsplit('.') {
+            let Some(label) = self.label_ids.get(label) else {
+                return false;
+            };
+            current = match self.edges.get(&(current, *label)) {
+                Some(child) => *child,
+                None => return false,
+            };
+            if self.nodes[current as usize].kind == NodeKind::Domain {
+                return true;
+            }
+        }
+        self.nodes[current as usize].kind == NodeKind::Full
+    }
+
+    // Whether the rule adds nothing to the set: a `full` rule is covered if
+    // the name is matched, a `domain` rule if it or a parent is a `domain` rule
+    pub fn covers(&self, rule: &Rule) -> bool {
+        match rule.rule_type {
+            RuleType::Full => self.contains(&rule.rule_content),
+            RuleType::Domain => {
+                let mut current = ROOT;
+                for label in rule.rule_content.rsplit('.') {
+                    let next = self
+                        .label_ids
+                        .get(label)
+                        .and_then(|label| self.edges.get(&(current, *label)));
+                    match next {
+                        Some(next) => current = *next,
+                        None => return false,
+                    }
+                    if self.nodes[current as usize].kind == NodeKind::Domain {
+                        return true;
+                    }
+                }
+                false
+            }
+            RuleType::Keyword | RuleType::Regex => false,
+        }
+    }
+
+    // Rules in reversed label order, a parent comes before its children
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            rule_set: self,
+            stack: vec![(ROOT, 0)],
+            path: vec![],
+        }
+    }
+
+    fn find(&self, domain: &str) -> Option<u32> {
+        let mut current = ROOT;
+        for label in domain.rsplit('.') {
+            let label = self.label_ids.get(label)?;
+            current = *self.edges.get(&(current, *label))?;
+        }
+        Some(current)
+    }
+
+    // Record `rule` as eliminated by the rule at node `id`, which is `depth`
+    // labels deep and so ends with the last `depth` labels of `rule`
+    fn record(&mut self, rule: &Rule, id: u32, depth: usize, reason: EliminationReason) {
+        if self.report.is_none() {
+            return;
+        }
+        let path = rule
+            .rule_content
+            .rsplit('.')
+            .take(depth)
+            .map(|label| self.label_ids[label])
+            .collect::<Vec<u32>>();
+        let Some(kept) = self.rule_at(id, &path) else {
+            return;
+        };
+        if let Some(report) = &mut self.report {
+            report.eliminated.push(Eliminated {
+                rule: rule.clone(),
+                kept,
+                reason,
+            });
+        }
+    }
+
+    // Record every rule below node `id` (and the `full` rule on it, if any)
+    // as eliminated by the `domain` rule being inserted there
+    fn record_subtree(&mut self, domain_rule: &Rule, id: u32, include_self: bool) {
+        let mut kept = domain_rule.clone();
+        kept.rule_type = RuleType::Domain;
+        let mut eliminated = vec![];
+        let mut stack = if include_self {
+            vec![(id, domain_rule.rule_content.clone())]
+        } else {
+            self.nodes[id as usize]
+                .children
+                .iter()
+                .map(|child| {
+                    (
+                        *child,
+                        self.child_content(*child, &domain_rule.rule_content),
+                    )
+                })
+                .collect()
+        };
+        while let Some((node_id, content)) = stack.pop() {
+            let node = &self.nodes[node_id as usize];
+            let rule_type = match node.kind {
+                NodeKind::Intermediate => None,
+                NodeKind::Full => Some(RuleType::Full),
+                NodeKind::Domain => Some(RuleType::Domain),
+            };
+            if let Some(rule_type) = rule_type {
+                let mut rule = Rule::new(rule_type, content.clone());
+                if let Some(provenance) = self.provenance.get(&node_id) {
+                    rule.provenance = provenance.clone();
+                }
+                eliminated.push(Eliminated {
+                    rule,
+                    kept: kept.clone(),
+                    reason: EliminationReason::Subsumed,
+                });
+            }
+            for child in &node.children {
+                stack.push((*child, self.child_content(*child, &content)));
+            }
+        }
+        if let Some(report) = &mut self.report {
+            report.eliminated.extend(eliminated);
+        }
+    }
+
+    fn child_content(&self, child: u32, parent_content: &str) -> String {
+        format!(
+            "{}.{}",
+            self.labels[self.nodes[child as usize].label as usize], parent_content
+        )
+    }
+
+    fn add_provenance(&mut self, id: u32, rule: &Rule) {
+        if !rule.provenance.is_empty() {
+            self.provenance
+                .entry(id)
+                .or_default()
+                .extend(rule.provenance.iter().cloned());
+        }
+    }
+
+    fn intern(&mut self, label: &str) -> u32 {
+        if let Some(id) = self.label_ids.get(label) {
+            return *id;
+        }
+        let id = self.labels.len() as u32;
+        self.labels.push(label.into());
+        self.label_ids.insert(label.into(), id);
+        id
+    }
+
+    fn add_node(&mut self, parent: u32, label: u32) -> u32 {
+        let node = Node {
+            label,
+            parent,
+            kind: NodeKind::Intermediate,
+            children: vec![],
+        };
+        let id = match self.free_nodes.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        };
+        self.nodes[parent as usize].children.push(id);
+        self.edges.insert((parent, label), id);
+        id
+    }
+
+    fn remove_children(&mut self, id: u32) {
+        let mut stack = std::mem::take(&mut self.nodes[id as usize].children);
+        while let Some(child) = stack.pop() {
+            let node = &mut self.nodes[child as usize];
+            if node.kind != NodeKind::Intermediate {
+                self.len -= 1;
+                self.provenance.remove(&child);
+            }
+            stack.append(&mut node.children);
+            let key = (node.parent, node.label);
+            self.edges.remove(&key);
+            self.free_nodes.push(child);
+        }
+    }
+
+    fn sorted_children(&self, id: u32) -> Vec<u32> {
+        let mut children = self.nodes[id as usize].children.clone();
+        children.sort_unstable_by(|a, b| {
+            self.labels[self.nodes[*a as usize].label as usize]
+                .cmp(&self.labels[self.nodes[*b as usize].label as usize])
+        });
+        children
+    }
+
+    fn rule_at(&self, id: u32, path: &[u32]) -> Option<Rule> {
+        let rule_type = match self.nodes[id as usize].kind {
+            NodeKind::Intermediate => return None,
+            NodeKind::Full => RuleType::Full,
+            NodeKind::Domain => RuleType::Domain,
+        };
+        let mut rule_content = String::new();
+        for label in path.iter().rev() {
+            if !rule_content.is_empty() {
+                rule_content.push('.');
+            }
+            rule_content.push_str(&self.labels[*label as usize]);
+        }
+        let mut rule = Rule::new(rule_type, rule_content);
+        if let Some(provenance) = self.provenance.get(&id) {
+            rule.provenance = provenance.clone();
+        }
+        Some(rule)
+    }
+}
+
+impl<'a> Extend<&'a Rule> for RuleSet {
+    fn extend<T: IntoIterator<Item = &'a Rule>>(&mut self, iter: T) {
+        for rule in iter {
+            self.insert(rule);
+        }
+    }
+}
+
+impl Extend<Rule> for RuleSet {
+    fn extend<T: IntoIterator<Item = Rule>>(&mut self, iter: T) {
+        for rule in iter {
+            self.insert(&rule);
+        }
+    }
+}
+
+impl FromIterator<Rule> for RuleSet {
+    fn from_iter<T: IntoIterator<Item = Rule>>(iter: T) -> Self {
+        let mut rule_set = RuleSet::new();
+        rule_set.extend(iter);
+        rule_set
+    }
+}
+
+impl<'a> IntoIterator for &'a RuleSet {
+    type Item = Rule;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a> {
+    rule_set: &'a RuleSet,
+    stack: Vec<(u32, usize)>, // (node, depth)
+    path: Vec<u32>,           // labels from the root to the current node
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Rule;
+
+    fn next(&mut self) -> Option<Rule> {
+        while let Some((id, depth)) = self.stack.pop() {
+            self.path.truncate(depth);
+            if id != ROOT {
+                self.path.push(self.rule_set.nodes[id as usize].label);
+            }
+            let children = self.rule_set.sorted_children(id);
+            self.stack.extend(
+                children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, self.path.len())),
+            );
+            if let Some(rule) = self.rule_set.rule_at(id, &self.path) {
+                return Some(rule);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_rule_set() {
+    let mut rule_set = RuleSet::new();
+    assert!(rule_set.insert(&Rule::new(RuleType::Full, "a.b.test.com".to_string())));
+    assert!(rule_set.insert(&Rule::new(RuleType::Full, "test.com".to_string())));
+    assert!(!rule_set.insert(&Rule::new(RuleType::Full, "test.com".to_string())));
+    assert_eq!(rule_set.len(), 2);
+
+    // domain: replaces full: on the same node and removes everything below
+    assert!(rule_set.insert(&Rule::new(RuleType::Domain, "test.com".to_string())));
+    assert_eq!(rule_set.len(), 1);
+    assert!(!rule_set.insert(&Rule::new(RuleType::Full, "c.test.com".to_string())));
+    assert!(!rule_set.insert(&Rule::new(RuleType::Domain, "d.test.com".to_string())));
+    assert!(!rule_set.insert(&Rule::new(RuleType::Full, "test.com".to_string())));
+    assert!(!rule_set.insert(&Rule::new(RuleType::Keyword, "test".to_string())));
+
+    assert!(rule_set.insert(&Rule::new(RuleType::Full, "b.example.com".to_string())));
+    assert!(rule_set.insert(&Rule::new(RuleType::Full, "a.example.com".to_string())));
+    assert_eq!(
+        rule_set
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "full:a.example.com",
+            "full:b.example.com",
+            "domain:test.com"
+        ]
+    );
+}
+
+#[test]
+fn test_rule_set_incremental() {
+    let mut rule_set = [
+        Rule::new(RuleType::Full, "a.example.com".to_string()),
+        Rule::new(RuleType::Full, "b.a.example.com".to_string()),
+        Rule::new(RuleType::Domain, "ads.net".to_string()),
+    ]
+    .into_iter()
+    .collect::<RuleSet>();
+    assert_eq!(rule_set.len(), 3);
+
+    assert!(rule_set.contains("a.example.com"));
+    assert!(!rule_set.contains("example.com"));
+    assert!(!rule_set.contains("c.a.example.com"));
+    assert!(rule_set.contains("ads.net"));
+    assert!(rule_set.contains("x.y.ads.net"));
+    assert!(!rule_set.contains("bads.net"));
+    assert!(rule_set.covers(&Rule::new(RuleType::Domain, "x.ads.net".to_string())));
+    assert!(!rule_set.covers(&Rule::new(RuleType::Domain, "a.example.com".to_string())));
+    assert!(rule_set.covers(&Rule::new(RuleType::Full, "a.example.com".to_string())));
+
+    // only the exact rule can be removed
+    assert!(!rule_set.remove(&Rule::new(RuleType::Domain, "a.example.com".to_string())));
+    assert!(!rule_set.remove(&Rule::new(RuleType::Full, "x.ads.net".to_string())));
+    assert!(rule_set.remove(&Rule::new(RuleType::Full, "a.example.com".to_string())));
+    assert!(!rule_set.contains("a.example.com"));
+    assert!(rule_set.contains("b.a.example.com"));
+    assert!(rule_set.remove(&Rule::new(RuleType::Full, "b.a.example.com".to_string())));
+    assert!(!rule_set.remove(&Rule::new(RuleType::Full, "b.a.example.com".to_string())));
+    assert_eq!(rule_set.len(), 1);
+
+    rule_set.extend(vec![
+        Rule::new(RuleType::Full, "a.example.com".to_string()),
+        Rule::new(RuleType::Domain, "x.ads.net".to_string()),
+    ]);
+    assert_eq!(
+        rule_set
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>(),
+        vec!["full:a.example.com", "domain:ads.net"]
+    );
+}
+
+#[test]
+fn test_rule_set_provenance() {
+    let provenance = |source: &str, line_number: usize, line: &str| Provenance {
+        source: source.into(),
+        line_number,
+        line: line.to_string(),
+    };
+    let mut rule_set = RuleSet::new();
+    rule_set.insert(
+        &Rule::new(RuleType::Full, "a.ads.com".to_string()).with_provenance(provenance(
+            "a.txt",
+            1,
+            "full:a.ads.com",
+        )),
+    );
+    rule_set.insert(
+        &Rule::new(RuleType::Domain, "ads.com".to_string()).with_provenance(provenance(
+            "a.txt",
+            2,
+            "domain:ads.com",
+        )),
+    );
+    rule_set.insert(
+        &Rule::new(RuleType::Domain, "ads.com".to_string()).with_provenance(provenance(
+            "b.txt",
+            7,
+            "||ads.com^",
+        )),
+    );
+    rule_set.insert(
+        &Rule::new(RuleType::Full, "b.ads.com".to_string()).with_provenance(provenance(
+            "b.txt",
+            8,
+            "full:b.ads.com",
+        )),
+    );
+
+    let rules = rule_set.iter().collect::<Vec<Rule>>();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].sources(), vec!["a.txt", "b.txt"]);
+    assert_eq!(
+        rules[0].provenance,
+        vec![
+            provenance("a.txt", 2, "domain:ads.com"),
+            provenance("b.txt", 7, "||ads.com^")
+        ]
+    );
+}
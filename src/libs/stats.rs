@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use super::{
+    config::Config,
+    download::SourceContent,
+    rule::{Rule, RuleType},
+    rule_set::RuleSet,
+};
+
+pub struct SourceStats {
+    pub location: String,
+    pub total_rules: usize,  // lines the source's format parses into a rule
+    pub valid_rules: usize,  // of those, rules naming a well-formed domain
+    pub merged_rules: usize, // rules left after merging the source with itself
+    pub unique_rules: usize, // merged rules no other source covers
+}
+
+// Redundancy of the accept(true) or reject(false) sources
+pub struct StatsReport {
+    pub accept_rule: bool,
+    pub sources: Vec<SourceStats>,
+    pub overlap: Vec<Vec<usize>>, // overlap[i][j]: rules of source i covered by source j
+}
+
+impl StatsReport {
+    pub fn new(config: &Config, sources: &[SourceContent], accept_rule: bool) -> Self {
+        let mut stats = vec![];
+        let mut rule_sets = vec![];
+        for (rule_src, source) in config.rule_src.iter().zip(sources) {
+            if !rule_src.provides(accept_rule) {
+                continue;
+            }
+            let rules = rule_src.src_type.parse_source(source, accept_rule);
+            let total_rules = rules.len();
+            let rules: Vec<Rule> = rules.into_iter().filter(is_valid).collect();
+            stats.push(SourceStats {
+                location: source.location.clone(),
+                total_rules,
+                valid_rules: rules.len(),
+                merged_rules: 0,
+                unique_rules: 0,
+            });
+            rule_sets.push(rules.into_iter().collect::<RuleSet>());
+        }
+
+        let merged = rule_sets
+            .iter()
+            .map(|rule_set| rule_set.iter().collect::<Vec<Rule>>())
+            .collect::<Vec<_>>();
+        let mut overlap = vec![vec![0; rule_sets.len()]; rule_sets.len()];
+        for (i, rules) in merged.iter().enumerate() {
+            let others = rule_sets
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .flat_map(|(_, rule_set)| rule_set.iter())
+                .collect::<RuleSet>();
+            for (j, rule_set) in rule_sets.iter().enumerate() {
+                if i != j {
+                    overlap[i][j] = rules.iter().filter(|rule| rule_set.covers(rule)).count();
+                }
+            }
+            overlap[i][i] = rules.len();
+            stats[i].merged_rules = rules.len();
+            stats[i].unique_rules = rules.iter().filter(|rule| !others.covers(rule)).count();
+        }
+
+        StatsReport {
+            accept_rule,
+            sources: stats,
+            overlap,
+        }
+    }
+}
+
+// Keyword and regex rules are taken as they are, domain and full rules need
+// dot separated labels of letters, digits, `-` and `_`
+fn is_valid(rule: &Rule) -> bool {
+    match rule.rule_type {
+        RuleType::Keyword | RuleType::Regex => true,
+        RuleType::Domain | RuleType::Full => rule.rule_content.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }),
+    }
+}
+
+impl Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} sources:",
+            if self.accept_rule { "Accept" } else { "Reject" }
+        )?;
+        writeln!(
+            f,
+            "  {:>3} {:>10} {:>10} {:>10} {:>10}  source",
+            "#", "total", "valid", "merged", "unique"
+        )?;
+        for (i, source) in self.sources.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>3} {:>10} {:>10} {:>10} {:>10}  {}",
+                i,
+                source.total_rules,
+                source.valid_rules,
+                source.merged_rules,
+                source.unique_rules,
+                source.location
+            )?;
+        }
+        if self.sources.len() > 1 {
+            writeln!(f, "  Overlap (rules of row covered by column):")?;
+            write!(f, "  {:>3}", "")?;
+            for j in 0..self.sources.len() {
+                write!(f, " {:>10}", j)?;
+            }
+            writeln!(f)?;
+            for (i, row) in self.overlap.iter().enumerate() {
+                write!(f, "  {:>3}", i)?;
+                for covered in row {
+                    write!(f, " {:>10}", covered)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stats() {
+    use super::config::RuleSrc;
+
+    let config = Config::new(vec![
+        RuleSrc::from_mosdns_file("a.txt".to_string(), false, true),
+        RuleSrc::from_mosdns_file("b.txt".to_string(), false, true),
+        RuleSrc::from_mosdns_file("c.txt".to_string(), true, true),
+    ]);
+    let sources = vec![
+        SourceContent::new(
+            "a.txt".to_string(),
+            "# list a\n! not a rule\ndomain:ads.com\nfull:x.ads.com\nfull:a.net\nfull:not a domain\n"
+                .to_string(),
+        ),
+        SourceContent::new(
+            "b.txt".to_string(),
+            "full:y.ads.com\nfull:a.net\nfull:b.net\n".to_string(),
+        ),
+        SourceContent::new("c.txt".to_string(), "domain:good.com\n".to_string()),
+    ];
+    let report = StatsReport::new(&config, &sources, false);
+    assert_eq!(report.sources.len(), 2);
+    let a = &report.sources[0];
+    assert_eq!(
+        (a.total_rules, a.valid_rules, a.merged_rules, a.unique_rules),
+        (4, 3, 2, 1)
+    );
+    let b = &report.sources[1];
+    assert_eq!(
+        (b.total_rules, b.valid_rules, b.merged_rules, b.unique_rules),
+        (3, 3, 3, 1)
+    );
+    assert_eq!(report.overlap, vec![vec![2, 1], vec![2, 3]]);
+
+    // headers, `!` comments and exceptions aren't rules of a block list
+    let url = "https://example.com/filter.txt".to_string();
+    let config = Config::new(vec![RuleSrc::from_adguard_home_rule(url.clone(), true)]);
+    let sources = vec![SourceContent::new(
+        url,
+        "[Adblock Plus 2.0]\n! Title: filter\n||ads.com^\n@@||ok.com^\n".to_string(),
+    )];
+    let report = StatsReport::new(&config, &sources, false);
+    assert_eq!(report.sources[0].total_rules, 1);
+}
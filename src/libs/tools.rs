@@ -1,101 +1,75 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+use std::cmp::Ordering;
+
+use log::warn;
+
+use serde::{Deserialize, Serialize};
 
 use crate::libs::rule::RuleType;
 
-use super::rule::Rule;
+use super::{report::MergeReport, rule::Rule, rule_set::RuleSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputOrder {
+    #[default]
+    ReversedLabels, // com.example.a < com.example.b < net.example
+    Domain, // a.example.com < b.example.com < example.net
+}
+
+impl OutputOrder {
+    pub fn compare(&self, a: &Rule, b: &Rule) -> Ordering {
+        let content_order = match self {
+            OutputOrder::ReversedLabels => {
+                a.rule_content.rsplit('.').cmp(b.rule_content.rsplit('.'))
+            }
+            OutputOrder::Domain => a.rule_content.cmp(&b.rule_content),
+        };
+        content_order.then_with(|| (a.rule_type as u8).cmp(&(b.rule_type as u8)))
+    }
+}
 
 // Merge and remove duplicates
 pub fn merge_and_remove_duplicates(rules_vec: Vec<Vec<Rule>>) -> Vec<Rule> {
-    let rules_tree = Rc::new(RefCell::new(Domain {
-        domain: "".to_string(),
-        domain_type: false,
-        is_rule: false,
-        children: HashMap::new(),
-    }));
+    merge_and_remove_duplicates_with_order(rules_vec, OutputOrder::default())
+}
+
+// Merge and remove duplicates, the result is sorted by `order`
+pub fn merge_and_remove_duplicates_with_order(
+    rules_vec: Vec<Vec<Rule>>,
+    order: OutputOrder,
+) -> Vec<Rule> {
+    merge(RuleSet::new(), rules_vec, order).0
+}
+
+// Merge and remove duplicates, also reporting every rule that was eliminated
+pub fn merge_with_report(
+    rules_vec: Vec<Vec<Rule>>,
+    order: OutputOrder,
+) -> (Vec<Rule>, MergeReport) {
+    let (rules, report) = merge(RuleSet::with_report(), rules_vec, order);
+    (rules, report.unwrap_or_default())
+}
 
+fn merge(
+    mut rule_set: RuleSet,
+    rules_vec: Vec<Vec<Rule>>,
+    order: OutputOrder,
+) -> (Vec<Rule>, Option<MergeReport>) {
     for rules in rules_vec {
         for rule in rules {
             if rule.rule_type == RuleType::Keyword || rule.rule_type == RuleType::Regex {
-                eprintln!("Keyword and Regex are not supported, skipping");
+                warn!("Keyword and Regex are not supported, skipping");
                 continue;
             }
-            let mut rule_domain = rule.rule_content.split('.').collect::<Vec<&str>>();
-            rule_domain.reverse();
-            let rule_type = rule.rule_type;
-            let mut current_domain = rules_tree.clone();
-            for domain in rule_domain {
-                let mut found = false;
-                let mut current_domain_temp = current_domain.clone();
-                if current_domain.borrow().children.contains_key(domain) {
-                    current_domain_temp = current_domain.borrow().children[domain].clone();
-                    found = true;
-                }
-                current_domain = current_domain_temp.clone();
-                if !found {
-                    let new_domain = Domain {
-                        domain: domain.to_string(),
-                        domain_type: false,
-                        is_rule: false,
-                        children: HashMap::new(),
-                    };
-                    current_domain
-                        .borrow_mut()
-                        .children
-                        .insert(domain.to_string(), Rc::new(RefCell::new(new_domain)));
-                    current_domain_temp = current_domain.borrow().children[domain].clone();
-                    current_domain = current_domain_temp.clone();
-                }
-            }
-            current_domain.borrow_mut().domain_type = match rule_type {
-                RuleType::Domain => true,
-                RuleType::Full => false,
-                RuleType::Keyword => panic!("Keyword is not supported"),
-                RuleType::Regex => panic!("Regex is not supported"),
-            };
-            current_domain.borrow_mut().is_rule = true;
-            if current_domain.borrow_mut().domain_type {
-                current_domain.borrow_mut().children = HashMap::new();
-            }
-        }
-    }
-    let mut rules = vec![];
-
-    // convert rules_tree to rules
-    let mut stack: Vec<(Rc<RefCell<Domain>>, usize)> = vec![]; // (domain, deepth)
-    stack.push((rules_tree.clone(), 0));
-    let mut full_domain: Vec<String> = vec![];
-    while !stack.is_empty() {
-        let current_domain = stack.pop().unwrap();
-        while full_domain.len() > current_domain.1 {
-            full_domain.pop();
-        }
-        full_domain.push(current_domain.0.borrow().domain.clone());
-        if current_domain.0.borrow().is_rule {
-            let mut full_domain_t = full_domain.clone();
-            full_domain_t.reverse();
-            full_domain_t.pop();
-            rules.push(Rule {
-                rule_type: if current_domain.0.borrow().domain_type {
-                    RuleType::Domain
-                } else {
-                    RuleType::Full
-                },
-                rule_content: full_domain_t.join("."),
-            });
-        }
-        if current_domain.0.borrow().children.len() != 0 {
-            stack.extend(
-                current_domain
-                    .0
-                    .borrow()
-                    .children
-                    .iter()
-                    .map(|(_k, v)| (v.clone(), current_domain.1 + 1)),
-            );
+            rule_set.insert(&rule);
         }
     }
 
-    rules
+    let mut rules = rule_set.iter().collect::<Vec<Rule>>();
+    if order != OutputOrder::ReversedLabels {
+        rules.sort_by(|a, b| order.compare(a, b));
+    }
+    (rules, rule_set.take_report())
 }
 
 #[test]
@@ -124,10 +98,103 @@ fn test_merge() {
     assert_eq!(rules.len(), 4);
 }
 
-#[derive(Debug)]
-struct Domain {
-    domain: String,
-    domain_type: bool, // true: 后续子节点都匹配 ,false: 后续子节点不匹配
-    is_rule: bool,     // true: 该节点是规则节点, false: 该节点不是规则节点(是中间节点)
-    children: HashMap<String, Rc<RefCell<Domain>>>,
+#[test]
+fn test_merge_order() {
+    let rules = || {
+        vec![
+            Rule::new(RuleType::Full, "b.example.com".to_string()),
+            Rule::new(RuleType::Domain, "example.net".to_string()),
+            Rule::new(RuleType::Full, "a.example.com".to_string()),
+            Rule::new(RuleType::Domain, "example.com.cn".to_string()),
+            Rule::new(RuleType::Full, "example.com".to_string()),
+        ]
+    };
+    let to_strings = |rules: Vec<Rule>| {
+        rules
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let merged = to_strings(merge_and_remove_duplicates(vec![rules()]));
+    assert_eq!(
+        merged,
+        vec![
+            "domain:example.com.cn",
+            "full:example.com",
+            "full:a.example.com",
+            "full:b.example.com",
+            "domain:example.net",
+        ]
+    );
+    let mut reversed = rules();
+    reversed.reverse();
+    assert_eq!(
+        to_strings(merge_and_remove_duplicates(vec![reversed])),
+        merged
+    );
+
+    let merged = to_strings(merge_and_remove_duplicates_with_order(
+        vec![rules()],
+        OutputOrder::Domain,
+    ));
+    assert_eq!(
+        merged,
+        vec![
+            "full:a.example.com",
+            "full:b.example.com",
+            "full:example.com",
+            "domain:example.com.cn",
+            "domain:example.net",
+        ]
+    );
+}
+
+#[test]
+fn test_merge_report() {
+    use super::report::EliminationReason;
+
+    let (rules, report) = merge_with_report(
+        vec![
+            vec![
+                Rule::new(RuleType::Full, "a.b.c.test.com".to_string()),
+                Rule::new(RuleType::Full, "test.com".to_string()),
+            ],
+            vec![
+                Rule::new(RuleType::Domain, "test.com".to_string()),
+                Rule::new(RuleType::Full, "x.test.com".to_string()),
+                Rule::new(RuleType::Domain, "test.com".to_string()),
+            ],
+        ],
+        OutputOrder::default(),
+    );
+    assert_eq!(rules.len(), 1);
+    let eliminated = report
+        .eliminated
+        .iter()
+        .map(|eliminated| {
+            (
+                eliminated.rule.to_string(),
+                eliminated.kept.to_string(),
+                eliminated.reason,
+            )
+        })
+        .collect::<Vec<_>>();
+    let subsumed = |rule: &str| {
+        (
+            rule.to_string(),
+            "domain:test.com".to_string(),
+            EliminationReason::Subsumed,
+        )
+    };
+    assert_eq!(eliminated.len(), 4);
+    assert!(eliminated.contains(&subsumed("full:test.com")));
+    assert!(eliminated.contains(&subsumed("full:a.b.c.test.com")));
+    assert!(eliminated.contains(&subsumed("full:x.test.com")));
+    assert!(eliminated.contains(&(
+        "domain:test.com".to_string(),
+        "domain:test.com".to_string(),
+        EliminationReason::Duplicate
+    )));
+    assert_eq!(report.totals_per_source().get("unknown"), Some(&4));
 }
@@ -0,0 +1,428 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Url,
+};
+
+use super::{
+    config::{Config, Direction, RuleSrcType},
+    format::RuleFormat,
+    integrity::{check_public_key, Integrity, OnFailure},
+};
+
+// A problem found in a config, `location` is the path of the field such as
+// `rule_src[1].url`
+pub struct ConfigIssue {
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+// Every problem found in the config, without fetching any source
+pub fn validate(config: &Config) -> Vec<ConfigIssue> {
+    let mut issues = vec![];
+    let mut issue =
+        |location: String, message: String| issues.push(ConfigIssue { location, message });
+
+    for (index, rule_src) in config.rule_src.iter().enumerate() {
+        let location = match &rule_src.name {
+            Some(name) => format!("rule_src[{}] ({})", index, name),
+            None => format!("rule_src[{}]", index),
+        };
+        if !rule_src.enabled {
+            continue;
+        }
+        if let Some(http) = &rule_src.http {
+            if !rule_src.src_type.is_remote() {
+                issue(
+                    format!("{}.http", location),
+                    "only remote sources are downloaded over HTTP".to_string(),
+                );
+            }
+            for (field, message) in check_http(http.proxy.as_deref(), &http.headers, http.timeout) {
+                issue(format!("{}.http.{}", location, field), message);
+            }
+        }
+        if let Some(integrity) = &rule_src.integrity {
+            let cached = config.cache_dir.is_some() && rule_src.src_type.is_remote();
+            for (field, message) in check_integrity(integrity, cached) {
+                issue(format!("{}.integrity.{}", location, field), message);
+            }
+        }
+        match &rule_src.src_type {
+            RuleSrcType::MosdnsFile { path } | RuleSrcType::PureFile { path } => {
+                if !Path::new(path).is_file() {
+                    issue(
+                        format!("{}.path", location),
+                        format!("no such file {}", path),
+                    );
+                }
+                if rule_src.direction == Direction::Both {
+                    issue(
+                        format!("{}.direction", location),
+                        "`both` needs a format marking allow rules, such as `adguard`".to_string(),
+                    );
+                }
+            }
+            RuleSrcType::AdguardHomeRule { url, mirrors, .. } => {
+                if let Err(e) = check_url(url) {
+                    issue(format!("{}.url", location), e);
+                }
+                for (mirror_index, mirror) in mirrors.iter().enumerate() {
+                    if let Err(e) = check_url(mirror) {
+                        issue(format!("{}.mirrors[{}]", location, mirror_index), e);
+                    } else if mirror == url || mirrors[..mirror_index].contains(mirror) {
+                        issue(
+                            format!("{}.mirrors[{}]", location, mirror_index),
+                            format!("{} is listed twice", mirror),
+                        );
+                    }
+                }
+            }
+            RuleSrcType::Files { path, format } => {
+                if let Err(e) = check_pattern(path) {
+                    issue(format!("{}.path", location), e);
+                }
+                // detected formats may differ from file to file
+                if rule_src.direction == Direction::Both && *format != Some(RuleFormat::Adguard) {
+                    issue(
+                        format!("{}.direction", location),
+                        "`both` needs every file in a format marking allow rules, set `format` to `adguard`"
+                            .to_string(),
+                    );
+                }
+                if rule_src.integrity.is_some() {
+                    issue(
+                        format!("{}.integrity", location),
+                        "`files` sources are read file by file and can't be verified".to_string(),
+                    );
+                }
+                if rule_src.member.is_some() {
+                    issue(
+                        format!("{}.member", location),
+                        "`files` sources read every file whole, archives must hold one file"
+                            .to_string(),
+                    );
+                }
+            }
+            RuleSrcType::Geosite { url, .. } => {
+                if let Err(e) = check_url(url) {
+                    issue(format!("{}.url", location), e);
+                }
+                issue(
+                    format!("{}.type", location),
+                    "geosite sources are not supported yet".to_string(),
+                );
+            }
+            RuleSrcType::Unknown => {
+                issue(
+                    format!("{}.type", location),
+                    "unknown source type".to_string(),
+                );
+            }
+        }
+    }
+
+    let http = &config.http;
+    for (field, message) in check_http(http.proxy.as_deref(), &http.headers, Some(http.timeout)) {
+        issue(format!("http.{}", field), message);
+    }
+
+    if normalize(&config.accept_rule_path) == normalize(&config.reject_rule_path) {
+        issue(
+            "reject_rule_path".to_string(),
+            format!(
+                "accept and reject rules are both written to {}",
+                config.reject_rule_path
+            ),
+        );
+    }
+    let mut outputs = vec![
+        ("accept_rule_path", config.accept_rule_path.as_str()),
+        ("reject_rule_path", config.reject_rule_path.as_str()),
+    ];
+    if let Some(geosite) = &config.geosite {
+        outputs.push(("geosite.path", geosite.path.as_str()));
+    }
+    if let Some(path) = &config.provenance_map_path {
+        outputs.push(("provenance_map_path", path));
+    }
+    if let Some(path) = &config.merge_report_path {
+        outputs.push(("merge_report_path", path));
+    }
+    if let Some(path) = &config.run_report_path {
+        outputs.push(("run_report_path", path));
+    }
+    if let Some(path) = &config.lock_path {
+        outputs.push(("lock_path", path));
+    }
+    // accept and reject pointing to the same file is reported above
+    let normalized: Vec<PathBuf> = outputs.iter().map(|(_, path)| normalize(path)).collect();
+    for (index, (field, path)) in outputs.iter().enumerate().skip(2) {
+        if let Some(other) = normalized[..index]
+            .iter()
+            .position(|other| *other == normalized[index])
+        {
+            let other_field = outputs[other].0;
+            issue(
+                field.to_string(),
+                format!("{} is also written to by {}", path, other_field),
+            );
+        }
+    }
+
+    if let Some(cache_dir) = &config.cache_dir {
+        if let Err(e) = check_dir(cache_dir) {
+            issue("cache_dir".to_string(), e);
+        }
+    }
+    if let Some(vendor_dir) = &config.vendor_dir {
+        if !Path::new(vendor_dir).is_dir() {
+            issue(
+                "vendor_dir".to_string(),
+                format!("no such directory {}", vendor_dir),
+            );
+        }
+    }
+    let remote = config.has_remote_sources();
+    if config.offline && remote && config.cache_dir.is_none() && config.vendor_dir.is_none() {
+        issue(
+            "offline".to_string(),
+            "remote sources need `cache_dir` or `vendor_dir` to build offline".to_string(),
+        );
+    }
+    // `--locked` restores remote sources from the cache only
+    if config.lock_path.is_some() && remote && config.cache_dir.is_none() {
+        issue(
+            "lock_path".to_string(),
+            "remote sources need `cache_dir` to be restored from the lock".to_string(),
+        );
+    }
+    issues
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        Ok(parsed) => Err(format!("unsupported scheme {} in {}", parsed.scheme(), url)),
+        Err(e) => Err(format!("malformed URL {}: {}", url, e)),
+    }
+}
+
+// Problems of an `http` section as (field, message)
+fn check_http(
+    proxy: Option<&str>,
+    headers: &BTreeMap<String, String>,
+    timeout: Option<u64>,
+) -> Vec<(String, String)> {
+    let mut issues = vec![];
+    if let Some(proxy) = proxy.filter(|proxy| proxy.contains("://")) {
+        match Url::parse(proxy) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") => (),
+            Ok(url) => issues.push((
+                "proxy".to_string(),
+                format!("unsupported proxy scheme {}", url.scheme()),
+            )),
+            Err(e) => issues.push(("proxy".to_string(), format!("malformed proxy URL: {}", e))),
+        }
+    }
+    for (name, value) in headers {
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            issues.push((
+                format!("headers.{}", name),
+                "invalid header name".to_string(),
+            ));
+        } else if HeaderValue::from_str(value).is_err() {
+            issues.push((
+                format!("headers.{}", name),
+                "invalid header value".to_string(),
+            ));
+        }
+    }
+    if timeout == Some(0) {
+        issues.push((
+            "timeout".to_string(),
+            "must be at least 1 second".to_string(),
+        ));
+    }
+    issues
+}
+
+// Problems of an `integrity` section as (field, message), `cached` tells
+// whether there is a cached copy to fall back to
+fn check_integrity(integrity: &Integrity, cached: bool) -> Vec<(String, String)> {
+    let mut issues = vec![];
+    if let Some(sha256) = &integrity.sha256 {
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            issues.push((
+                "sha256".to_string(),
+                "must be 64 hexadecimal digits".to_string(),
+            ));
+        }
+    }
+    for (field, url) in [
+        ("checksum_url", &integrity.checksum_url),
+        ("signature_url", &integrity.signature_url),
+    ] {
+        if let Some(Err(e)) = url.as_deref().map(check_url) {
+            issues.push((field.to_string(), e));
+        }
+    }
+    for (index, public_key) in integrity.public_keys.iter().enumerate() {
+        if let Err(e) = check_public_key(public_key) {
+            issues.push((format!("public_keys[{}]", index), e));
+        }
+    }
+    match (&integrity.signature_url, integrity.public_keys.is_empty()) {
+        (Some(_), true) => issues.push((
+            "public_keys".to_string(),
+            "a signature needs at least one public key to verify it".to_string(),
+        )),
+        (None, false) => issues.push((
+            "signature_url".to_string(),
+            "public keys need a signature to verify".to_string(),
+        )),
+        _ => (),
+    }
+    if integrity.on_failure == OnFailure::Cache && !cached {
+        issues.push((
+            "on_failure".to_string(),
+            "`cache` needs `cache_dir` and a remote source".to_string(),
+        ));
+    }
+    issues
+}
+
+// A directory, or a glob pattern whose fixed leading directories exist
+fn check_pattern(pattern: &str) -> Result<(), String> {
+    if Path::new(pattern).is_dir() {
+        return Ok(());
+    }
+    glob::Pattern::new(pattern).map_err(|e| format!("invalid pattern {}: {}", pattern, e))?;
+    let Some(wildcard) = pattern.find(['*', '?', '[']) else {
+        return Err(format!("no such directory {}", pattern));
+    };
+    let dir = match pattern[..wildcard].rfind('/') {
+        Some(0) => "/",
+        Some(slash) => &pattern[..slash],
+        None => ".",
+    };
+    if Path::new(dir).is_dir() {
+        Ok(())
+    } else {
+        Err(format!("no such directory {}", dir))
+    }
+}
+
+// The directory must be writable, or creatable under its nearest existing
+// ancestor, which is tried by creating a file in it
+fn check_dir(dir: &str) -> Result<(), String> {
+    let mut path = Path::new(dir);
+    loop {
+        if let Ok(metadata) = path.metadata() {
+            if !metadata.is_dir() {
+                return Err(format!("{} is not a directory", path.display()));
+            }
+            let probe = path.join(format!(".easy_adblock_check_{}", std::process::id()));
+            return match std::fs::File::create_new(&probe) {
+                Ok(_) => {
+                    std::fs::remove_file(&probe).map_err(|e| format!("{}: {}", probe.display(), e))
+                }
+                Err(e) => Err(format!("{} is not writable: {}", path.display(), e)),
+            };
+        }
+        path = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."), // relative path, created in the working directory
+        };
+    }
+}
+
+// The path with `.` and `..` resolved, from the working directory if it is
+// relative, so that `out.txt` and `./out.txt` compare equal
+fn normalize(path: &str) -> PathBuf {
+    let mut normalized = std::env::current_dir().unwrap_or_default();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[test]
+fn test_validate() {
+    use super::config::RuleSrc;
+
+    let mut config = Config::new(vec![
+        RuleSrc::from_mosdns_file("/nonexistent/allow.txt".to_string(), true, true),
+        RuleSrc::from_adguard_home_rule("example.com/rules.txt".to_string(), true),
+        RuleSrc::from_adguard_home_rule("https://example.com/rules.txt".to_string(), true),
+    ]);
+    if let RuleSrcType::AdguardHomeRule { mirrors, .. } = &mut config.rule_src[2].src_type {
+        *mirrors = vec![
+            "ftp://example.com/rules.txt".to_string(),
+            "https://example.com/rules.txt".to_string(),
+        ];
+    }
+    let mut files = RuleSrc::new(
+        RuleSrcType::Files {
+            path: "/nonexistent/custom.d/*.txt".to_string(),
+            format: Some(RuleFormat::Pure),
+        },
+        Direction::Both,
+        true,
+    );
+    files.member = Some("ads.txt".to_string());
+    config.add(files);
+    config.reject_rule_path = config.accept_rule_path.clone();
+    config.merge_report_path = Some("report/../accept.txt".to_string());
+    config.cache_dir = Some("Cargo.toml/cache".to_string());
+    config.rule_src[2].integrity = Some(Integrity {
+        sha256: Some("abc".to_string()),
+        public_keys: vec!["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string()],
+        ..Integrity::default()
+    });
+
+    let issues: Vec<String> = validate(&config).iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        issues,
+        vec![
+            "rule_src[0].path: no such file /nonexistent/allow.txt",
+            "rule_src[1].url: malformed URL example.com/rules.txt: relative URL without a base",
+            "rule_src[2].integrity.sha256: must be 64 hexadecimal digits",
+            "rule_src[2].integrity.signature_url: public keys need a signature to verify",
+            "rule_src[2].mirrors[0]: unsupported scheme ftp in ftp://example.com/rules.txt",
+            "rule_src[2].mirrors[1]: https://example.com/rules.txt is listed twice",
+            "rule_src[3].path: no such directory /nonexistent/custom.d",
+            "rule_src[3].direction: `both` needs every file in a format marking allow rules, set `format` to `adguard`",
+            "rule_src[3].member: `files` sources read every file whole, archives must hold one file",
+            "reject_rule_path: accept and reject rules are both written to ./accept.txt",
+            "merge_report_path: report/../accept.txt is also written to by accept_rule_path",
+            "cache_dir: Cargo.toml is not a directory",
+        ]
+    );
+
+    // a lock of remote sources can't be restored without the cache
+    config.cache_dir = None;
+    config.lock_path = Some("./rules.lock".to_string());
+    let issues: Vec<String> = validate(&config).iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        issues.last().unwrap(),
+        "lock_path: remote sources need `cache_dir` to be restored from the lock"
+    );
+}
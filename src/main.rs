@@ -1,46 +1,375 @@
-use std::{env, io::Write};
+use std::{process::ExitCode, time::Duration};
 
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand};
 use easy_adblock_for_mosdns::libs::{
-    config::Config, download::Update, tools::merge_and_remove_duplicates,
+    build::build,
+    config::Config,
+    diff::RuleDiff,
+    download::SourceContent,
+    format::load_rule_file,
+    geosite::{default_accept_category, default_reject_category, GeositeOutput},
+    lock::Lock,
+    query::QueryReport,
+    request::HttpClient,
+    rule::Rule,
+    stats::StatsReport,
+    validate::validate,
 };
-use lazy_static::lazy_static;
+use log::{error, info};
 
-lazy_static!(
-    static ref HTTP_UA: String = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3".to_string();
-);
+#[derive(Parser)]
+#[command(version, about = "Merge ad blocking rule lists into mosdns rule files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Config file, same as --config (kept for `easy_adblock_for_mosdns config.json`)
+    #[arg(value_name = "CONFIG")]
+    config_path: Option<String>,
+
+    /// Config file
+    #[arg(short, long, global = true, default_value = "./config.json")]
+    config: String,
+
+    /// More logging, repeat for even more (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Merge the rules but don't write any output
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Never download, remote sources come from the cache or `vendor_dir`
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Use only the source contents recorded in the lock, remote ones from the cache
+    #[arg(long, global = true)]
+    locked: bool,
+
+    /// Write the accept rules here instead of `accept_rule_path`
+    #[arg(long, global = true, value_name = "PATH")]
+    accept_output: Option<String>,
+
+    /// Write the reject rules here instead of `reject_rule_path`
+    #[arg(long, global = true, value_name = "PATH")]
+    reject_output: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch every source, merge the rules and write the outputs (default)
+    Build,
+    /// Validate the config without fetching anything
+    Check,
+    /// Rewrite a config in the current schema
+    MigrateConfig {
+        /// Write the migrated config here instead of over the original
+        #[arg(long, value_name = "PATH")]
+        output: Option<String>,
+    },
+    /// Show every rule matching a domain and whether it is blocked
+    Query { domain: String },
+    /// Compare two rule files, or a rule file with a dry-run build
+    Diff {
+        old: String,
+        /// Compared with a dry-run build of the config if omitted
+        new: Option<String>,
+        /// Compare accept rules instead of reject rules
+        #[arg(long)]
+        accept: bool,
+        /// Print the difference as JSON
+        #[arg(long)]
+        json: bool,
+        /// Category read from geosite.dat files, the accept or reject category of the config by default
+        #[arg(long)]
+        category: Option<String>,
+    },
+    /// Show per source statistics and the overlap between sources
+    Stats,
+    /// Fetch every source and record them in the lock without building
+    UpdateLock,
+    /// Build, then rebuild every interval with the `auto_update` and `files` sources read again
+    Daemon {
+        /// Seconds between builds
+        #[arg(long, default_value_t = 86400, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+}
+
+// Exit codes of each failure class, clap exits with 2 on usage errors
+#[derive(Debug, Clone, Copy)]
+enum Failure {
+    Config = 3, // config can't be loaded
+    Fetch = 4,  // a rule source can't be fetched
+    Output = 5, // an output can't be written
+    Input = 6,  // a rule file or query can't be processed
+}
+
+impl From<Failure> for ExitCode {
+    fn from(failure: Failure) -> Self {
+        ExitCode::from(failure as u8)
+    }
+}
 
 #[tokio::main]
-async fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    let config_path = if args.len() > 1 {
-        args[1].as_str()
-    } else {
-        "./config.json"
-    };
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::Builder::new()
+        .filter_level(match cli.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        })
+        .init();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => failure.into(),
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Failure> {
+    let command = cli.command.unwrap_or(Command::Build);
+    let config_path = cli.config_path.as_deref().unwrap_or(&cli.config);
+    if let Command::Diff {
+        old,
+        new: Some(new),
+        accept,
+        json,
+        category,
+    } = &command
+    {
+        // nothing is fetched, the config only names the geosite categories
+        let config = if std::path::Path::new(config_path).exists() {
+            Some(Config::load(config_path).map_err(|e| {
+                error!("Failed to load config {}", e.chain());
+                Failure::Config
+            })?)
+        } else {
+            None
+        };
+        let geosite = config.as_ref().and_then(|config| config.geosite.as_ref());
+        let category = geosite_category(category.clone(), geosite, *accept);
+        let new_rules = load_rule_file(new, *accept, &category).map_err(|e| {
+            error!("Failed to read rule file {}", e.chain());
+            Failure::Input
+        })?;
+        return diff(old, new_rules, *accept, *json, &category);
+    }
 
-    let config = match Config::load(config_path) {
-        Ok(config) => config,
-        Err(e) => {
-            eprintln!("Failed to load config: {}", e);
-            return;
+    let mut config = Config::load(config_path).map_err(|e| {
+        error!("Failed to load config {}", e.chain());
+        Failure::Config
+    })?;
+    if let Command::MigrateConfig { output } = &command {
+        let output = output.as_deref().unwrap_or(config_path);
+        if cli.dry_run {
+            let content = config.render(output).map_err(|e| {
+                error!("Failed to render config {}", e.chain());
+                Failure::Output
+            })?;
+            print!("{}", content);
+            return Ok(());
         }
-    };
+        config.save(output).map_err(|e| {
+            error!("Failed to write config {}", e.chain());
+            Failure::Output
+        })?;
+        println!("{} migrated to {}", config_path, output);
+        return Ok(());
+    }
+    if let Some(path) = cli.accept_output {
+        config.accept_rule_path = path;
+    }
+    if let Some(path) = cli.reject_output {
+        config.reject_rule_path = path;
+    }
+    if cli.offline {
+        config.offline = true;
+    }
 
-    for accept_rule in [true, false] {
-        let mut file = std::fs::File::create(if accept_rule {
-            &config.accept_rule_path
+    let issues = validate(&config);
+    for issue in &issues {
+        if let Command::Check = command {
+            println!("{}: {}", config_path, issue);
         } else {
-            &config.reject_rule_path
-        })
-        .unwrap();
-        let mut rules_vec = vec![];
-        for rule_src in &config.rule_src {
-            let rules = rule_src.src_type.get(accept_rule).await.unwrap();
-            rules_vec.push(rules);
+            error!("{}: {}", config_path, issue);
         }
-        let rules = merge_and_remove_duplicates(rules_vec);
-        for rule in rules {
-            file.write_all(format!("{}\n", rule).as_bytes()).unwrap();
+    }
+    if !issues.is_empty() {
+        return Err(Failure::Config);
+    }
+    if let Command::Check = command {
+        println!("{}: ok", config_path);
+        return Ok(());
+    }
+
+    if cli.locked && matches!(command, Command::UpdateLock | Command::Daemon { .. }) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--locked can't be used with update-lock or daemon, they fetch the sources again",
+            )
+            .exit();
+    }
+
+    if matches!(command, Command::UpdateLock)
+        && config.has_remote_sources()
+        && config.cache_dir.is_none()
+    {
+        error!(
+            "{}: remote sources need `cache_dir` to be restored from the lock",
+            config_path
+        );
+        return Err(Failure::Config);
+    }
+
+    // query and stats use cached copies, builds (dry-run diffs too) fetch again
+    let prefer_cache = matches!(command, Command::Query { .. } | Command::Stats);
+    let client = config.http_client().map_err(|e| {
+        error!("Failed to create HTTP client {}", e.chain());
+        Failure::Config
+    })?;
+    let sources = if cli.locked {
+        let lock = Lock::load(config.lock_path()).map_err(|e| {
+            error!("Failed to load lock {}", e.chain());
+            Failure::Config
+        })?;
+        config.locked_sources(&client, &lock).await
+    } else {
+        config.fetch_sources(&client, prefer_cache).await
+    }
+    .map_err(|e| {
+        error!("Failed to fetch rule source {}", e.chain());
+        Failure::Fetch
+    })?;
+
+    match command {
+        Command::Query { domain } => {
+            let report = QueryReport::new(&config, &sources, &domain).map_err(|e| {
+                error!("Failed to query {}: {}", domain, e.chain());
+                Failure::Input
+            })?;
+            print!("{}", report);
+            Ok(())
+        }
+        Command::Stats => {
+            for accept_rule in [true, false] {
+                println!("{}", StatsReport::new(&config, &sources, accept_rule));
+            }
+            Ok(())
+        }
+        Command::Diff {
+            old,
+            accept,
+            json,
+            category,
+            ..
+        } => {
+            let output = build(&config, &sources, true).map_err(|e| {
+                error!("Failed to build {}", e.chain());
+                Failure::Output
+            })?;
+            let new_rules = if accept {
+                output.accept_rules
+            } else {
+                output.reject_rules
+            };
+            let category = geosite_category(category, config.geosite.as_ref(), accept);
+            diff(&old, new_rules, accept, json, &category)
+        }
+        Command::UpdateLock => {
+            let lock = Lock::new(&config.rule_src, &sources);
+            if !cli.dry_run {
+                lock.save(config.lock_path()).map_err(|e| {
+                    error!("Failed to write lock {}", e.chain());
+                    Failure::Output
+                })?;
+            }
+            println!(
+                "{} sources locked in {}",
+                lock.sources.len(),
+                config.lock_path()
+            );
+            Ok(())
         }
+        Command::Daemon { interval } => {
+            daemon(&config, &client, sources, cli.dry_run, interval).await
+        }
+        Command::Build | Command::Check | Command::MigrateConfig { .. } => {
+            build_once(&config, &sources, cli.dry_run)
+        }
+    }
+}
+
+fn build_once(config: &Config, sources: &[SourceContent], dry_run: bool) -> Result<(), Failure> {
+    let output = build(config, sources, dry_run).map_err(|e| {
+        error!("Failed to write output {}", e.chain());
+        Failure::Output
+    })?;
+    info!(
+        "{} accept rules, {} reject rules{}",
+        output.accept_rules.len(),
+        output.reject_rules.len(),
+        if dry_run { " (dry run)" } else { "" }
+    );
+    Ok(())
+}
+
+async fn daemon(
+    config: &Config,
+    client: &HttpClient,
+    mut sources: Vec<SourceContent>,
+    dry_run: bool,
+    interval: u64,
+) -> Result<(), Failure> {
+    build_once(config, &sources, dry_run)?;
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        // a failed refresh or build keeps the previous outputs until the next one
+        match config.refresh_sources(client, sources.clone()).await {
+            Ok(refreshed) => sources = refreshed,
+            Err(e) => {
+                error!("Failed to fetch rule source {}", e.chain());
+                continue;
+            }
+        }
+        let _ = build_once(config, &sources, dry_run);
+    }
+}
+
+// The category `diff` reads from a geosite.dat
+fn geosite_category(
+    category: Option<String>,
+    geosite: Option<&GeositeOutput>,
+    accept: bool,
+) -> String {
+    category.unwrap_or_else(|| match (geosite, accept) {
+        (Some(geosite), true) => geosite.accept_category.clone(),
+        (Some(geosite), false) => geosite.reject_category.clone(),
+        (None, true) => default_accept_category(),
+        (None, false) => default_reject_category(),
+    })
+}
+
+fn diff(
+    old: &str,
+    new_rules: Vec<Rule>,
+    accept: bool,
+    json: bool,
+    geosite_category: &str,
+) -> Result<(), Failure> {
+    let old_rules = load_rule_file(old, accept, geosite_category).map_err(|e| {
+        error!("Failed to read rule file {}", e.chain());
+        Failure::Input
+    })?;
+    let rule_diff = RuleDiff::new(old_rules, new_rules);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rule_diff).unwrap());
+    } else {
+        print!("{}", rule_diff);
     }
+    Ok(())
 }